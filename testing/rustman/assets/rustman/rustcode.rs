@@ -1,12 +1,20 @@
 // formatted with the following command: 
 // rustfmt rustcode.rs --edition 2024 --config max_width=50,array_width=50,blank_lines_upper_bound=0,chain_width=50,comment_width=50,fn_call_width=50,fn_params_layout="Compressed",fn_single_line=true,format_strings=true
 
+pub mod options;
+pub mod password;
+pub mod puzzle;
+pub mod replay;
+pub mod scores;
+pub mod snapshot;
+pub mod storage;
 use crate::npc::*;
 use crate::resources::entities::{
-    BlackFade, CharGFX, GPReady, GameTimer,
-    LifeMeterBox, RustCode, TetrisGrid,
+    BlackFade, CharGFX, ComboCounter, GPReady,
+    GameTimer, HoldBox, LifeMeterBox,
+    PasswordGrid, RustCode, TetrisGrid,
     TetrisGridPointState, TetrisPiece,
-    WeaponBoxSelect, WilyProp,
+    TetrisPieceSlot, WeaponBoxSelect, WilyProp,
 };
 use crate::resources::explosion_orb::*;
 use crate::resources::hitflash::*;
@@ -20,13 +28,187 @@ use crate::resources::spaceship::{
 };
 use crate::*;
 use macroquad::audio::set_sound_volume;
-/// time in seconds that must elapse for a tetris piece to move down
-const DROP_DOWN_SPEED: f32 = 0.75;
-/// time in seconds that must elapse before repeating a drop down
-const DROP_DOWN_CYCLE_TIME: f32 = 0.0125;
-// See line 3023 in MM2PA.py
+/// columns between the right edge of the tetris
+///  grid and the upcoming pieces
+const PREVIEW_COLUMN_OFFSET: i32 = 1;
+/// rows taken up by each upcoming piece in the
+///  preview
+const PREVIEW_SLOT_ROWS: i32 = 3;
+/// columns and rows from the top left of the
+///  tetris grid to the held piece, inside the
+///  hold box
+const HOLD_OFFSET: (i32, i32) = (-5, 1);
+/// alpha of the held piece while it can't be
+///  swapped back in
+const HOLD_UNAVAILABLE_ALPHA: f32 = 0.5;
+/// alpha of the ghost piece showing where the
+///  active piece will land
+const GHOST_ALPHA: f32 = 0.3;
+/// hitpoints a robot starts the fight with, a
+///  full life meter
+const ROBOT_MAX_HITPOINTS: u8 = 16;
+/// locks in a row that need to clear rows before
+///  the combo counter shows up
+const MIN_SHOWN_COMBO: u32 = 2;
+/// damage dealt for clearing every block off the
+///  tetris grid, whatever the weapon unless the
+///  robot is immune to it
+const PERFECT_CLEAR_DAMAGE: u8 = 8;
+/// name the player's runs are put in the best
+///  times table under. the rest of the table are
+///  other robots from the series, so the player's
+///  runs go in as the hero whose run it was
+const PLAYER_NAME: &str = "MEGA MAN";
+/// robot masters whose weapons Mega Man can take,
+///  in the order of the weapon menu
+const ROBOT_MASTERS: [NPCName; 8] = [
+    NPCName::BubbleMan,
+    NPCName::AirMan,
+    NPCName::QuickMan,
+    NPCName::HeatMan,
+    NPCName::WoodMan,
+    NPCName::MetalMan,
+    NPCName::FlashMan,
+    NPCName::CrashMan,
+];
+/// simulation ticks run every second of gameplay,
+///  whatever the frame rate
+const TICKS_PER_SECOND: u32 = 60;
+/// time simulated by each tick
+const TICK_TIME: f32 =
+    1. / TICKS_PER_SECOND as f32;
+/// most ticks caught up in one frame, past that
+///  the time is dropped instead of the game
+///  spiraling behind
+const MAX_TICKS_PER_FRAME: u32 = 5;
+/// seconds of a fight between snapshots of it,
+///  the most a closed window or browser tab can
+///  lose
+const SUSPEND_INTERVAL: f32 = 5.;
+/// the fixed rate clock the gameplay state runs
+///  on, kept apart from the frames it gets drawn
+///  in
+#[derive(Clone, Debug, Default)]
+pub struct Simulation {
+    /// frame time that hasn't been simulated yet
+    pub time_accumulator: f32,
+    /// key presses from frames too short to run a
+    ///  tick, held for the next tick so they
+    ///  aren't lost
+    pub pressed_actions: Vec<replay::Action>,
+    /// the game timer's reading when the stage
+    ///  started, the puzzle's clock runs on from
+    ///  it
+    pub game_timer_start: f32,
+    /// the puzzle's clock when the fight was last
+    ///  suspended
+    pub suspended_at: f32,
+    /// seconds since the stage's intro started,
+    ///  `None` until it does. the READY flashes
+    ///  for the first 3
+    pub intro_elapsed: Option<f32>,
+    /// seconds since the robot's life meter last
+    ///  filled a notch
+    pub life_meter_elapsed: f32,
+    /// how long each music track started this
+    ///  stage has played for
+    pub music_elapsed: Vec<(TrackName, f32)>,
+}
+impl Simulation {
+    /// how long `name` has played for this stage,
+    ///  0 until it's been playing for a tick
+    fn music_elapsed(
+        &self, name: &TrackName,
+    ) -> f32 {
+        self.music_elapsed
+            .iter()
+            .find(|(track_name, _)| {
+                track_name == name
+            })
+            .map_or(0., |(_, elapsed)| *elapsed)
+    }
+}
+/// run as many fixed [TICK_TIME] ticks as the
+///  frame time has built up. the input is read
+///  once per frame: held keys apply to every tick
+///  and presses to the next tick only, so a key
+///  is never lost or doubled
 pub async fn run(
     gameworld: &mut GameWorld,
+) -> GameState {
+    if !gameworld.gamestate.is_initialized {
+        return run_tick(gameworld, Vec::new())
+            .await;
+    }
+    gameworld.simulation.time_accumulator +=
+        get_frame_time();
+    let (held_actions, pressed_actions): (
+        Vec<_>,
+        Vec<_>,
+    ) = read_actions(gameworld)
+        .into_iter()
+        .partition(|action| action.is_held());
+    gameworld
+        .simulation
+        .pressed_actions
+        .extend(pressed_actions);
+    let mut ticks = 0;
+    while gameworld.simulation.time_accumulator
+        >= TICK_TIME
+    {
+        if ticks == MAX_TICKS_PER_FRAME {
+            gameworld
+                .simulation
+                .time_accumulator = 0.;
+            break;
+        }
+        gameworld.simulation.time_accumulator -=
+            TICK_TIME;
+        ticks += 1;
+        let mut actions = held_actions.clone();
+        actions.append(
+            &mut gameworld
+                .simulation
+                .pressed_actions,
+        );
+        let game_state =
+            run_tick(gameworld, actions).await;
+        // leaving the stage, stop ticking so the
+        //  next state starts fresh
+        if !matches!(
+            game_state.state,
+            State::Gameplay
+        ) || !game_state.is_initialized
+        {
+            return game_state;
+        }
+    }
+    // a window or browser tab can be closed
+    //  without any warning, so keep the fight
+    //  suspended as it goes. when the main loop
+    //  holds the window open to quit, suspend it
+    //  one last time
+    if is_quit_requested()
+        || gameworld.puzzle.time_elapsed
+            - gameworld.simulation.suspended_at
+            >= SUSPEND_INTERVAL
+    {
+        gameworld.simulation.suspended_at =
+            gameworld.puzzle.time_elapsed;
+        suspend(gameworld);
+    }
+    GameState {
+        state: State::Gameplay,
+        is_initialized: true,
+    }
+}
+// See line 3023 in MM2PA.py
+/// one tick of the gameplay state, `actions`
+///  being what the player did for [gameplay()] to
+///  use
+async fn run_tick(
+    gameworld: &mut GameWorld,
+    actions: Vec<replay::Action>,
 ) -> GameState {
     // initialization
     if !gameworld.gamestate.is_initialized {
@@ -39,6 +221,7 @@ pub async fn run(
             is_initialized: true,
         };
     }
+    advance_timers(gameworld);
     if gameworld
         .get_entities_by_type_and_property(
             |npc: &NPC| npc.npc_state,
@@ -65,8 +248,10 @@ pub async fn run(
         == NPCName::RustMan
     {
         // gameworld.get_entity_by_type::<entities::Credits>().position.y -= 0.5;
-        gameworld.get_entity_by_type::
-            <entities::RustCode>().position.y -= 0.5;
+        gameworld
+            .get_entity_by_type::<entities::RustCode>()
+            .position
+            .y -= 0.5;
     }
     if gameworld
         .get_entities_by_type_and_property(
@@ -193,6 +378,19 @@ pub async fn run(
             .alpha
             <= 0.
         {
+            // the run is over, unless it was a
+            //  replay being watched. only beating
+            //  Dr. Wily records a best time: the
+            //  game timer runs across every stage
+            //  of the run, and the table shipped
+            //  with the game holds whole runs, so
+            //  a single stage's clear has nothing
+            //  to be ranked against
+            if !matches!(gameworld.replay, replay::Session::Playing { .. }) {
+                if let Err(err) = record_best_time(gameworld).await {
+                    macroquad::logging::error!("failed to save the best time: {err}");
+                }
+            }
             return GameState {
                 state: State::Credits,
                 is_initialized: false,
@@ -203,13 +401,53 @@ pub async fn run(
             is_initialized: true,
         };
     }
-    gameplay(gameworld).await;
+    gameplay(gameworld, actions).await;
     return GameState {
         state: State::Gameplay,
         is_initialized: true,
     };
 }
-async fn gameplay(gameworld: &mut GameWorld) {
+/// move the stage's clocks on by one tick, so the
+///  intro, the life meter filling and the victory
+///  and defeat music keep the same pace as the
+///  puzzle whatever the frame rate. these are the
+///  only clocks the gameplay state reads, the
+///  entities' own frame time clocks are left to
+///  draw with
+fn advance_timers(gameworld: &mut GameWorld) {
+    let playing: Vec<TrackName> = gameworld
+        .get_entities_by_type::<MusicTrack>()
+        .iter()
+        .filter(|track| track.is_playing)
+        .map(|track| track.name.clone())
+        .collect();
+    let simulation = &mut gameworld.simulation;
+    if let Some(intro_elapsed) =
+        &mut simulation.intro_elapsed
+    {
+        *intro_elapsed += TICK_TIME;
+    }
+    simulation.life_meter_elapsed += TICK_TIME;
+    for name in playing {
+        match simulation
+            .music_elapsed
+            .iter_mut()
+            .find(|(track_name, _)| {
+                *track_name == name
+            }) {
+            Some((_, elapsed)) => {
+                *elapsed += TICK_TIME
+            }
+            None => simulation
+                .music_elapsed
+                .push((name, TICK_TIME)),
+        }
+    }
+}
+async fn gameplay(
+    gameworld: &mut GameWorld,
+    actions: Vec<replay::Action>,
+) {
     // gameplay
     // TODO: all the following:
     //  x capture and process keyboard events
@@ -243,178 +481,21 @@ async fn gameplay(gameworld: &mut GameWorld) {
     //  - quit dialog
     //   - Sequence: warp in/out rush
     // ...
-    // Check if there was a collision
-    if let Some(tetris_piece) = gameworld
-        .get_entities_by_type::<TetrisPiece>()
-        .first_mut()
-    {
-        // we check the active piece's turn time elapsed so that we still have a moment to move left/right
-        //  even when at bottom of tetris grid
-        if tetris_piece.turn_time_elapsed
-            >= DROP_DOWN_SPEED
-        {
-            if process_collisions(gameworld) {
-                let active_weapon = gameworld
-                    .get_entities_by_type::<MegaMan>()
-                    .first()
-                    .unwrap()
-                    .active_weapon
-                    .clone();
-                match active_weapon {
-                    Some(npc::NPCName::BubbleMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::BubbleShot).await
-                    }
-                    Some(npc::NPCName::AirMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::AirShot).await
-                    }
-                    Some(npc::NPCName::QuickMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::QuickShot).await
-                    }
-                    Some(npc::NPCName::HeatMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::HeatShot).await
-                    }
-                    Some(npc::NPCName::WoodMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::WoodShot).await
-                    }
-                    Some(npc::NPCName::MetalMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::MetalShot).await
-                    }
-                    Some(npc::NPCName::FlashMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::FlashShot).await
-                    }
-                    Some(npc::NPCName::CrashMan) => {
-                        gameworld.sfx_atlas.play(sfx::SFXName::CrashShot).await
-                    }
-                    _ => gameworld.sfx_atlas.play(sfx::SFXName::PShot).await,
-                }
-                gameworld
-                    .get_entities_by_type::<TetrisGrid>()
-                    .first_mut()
-                    .unwrap()
-                    .gfx_time_elapsed = 0.
-            }
-        }
-    }
-    // if there is not an active piece, insert one
-    // we use the tetris_grid_timer variable to determine if enough time has passed after clearning lines to spawn new tetris piece
-    let tetris_grid_timer = gameworld
-        .get_entities_by_type::<TetrisGrid>()
-        .first()
-        .unwrap()
-        .gfx_time_elapsed
-        .clone();
-    // TODO: I'm not sure the tetris_grid_timer is actually doing anything. It never gets reset anywhere.
-    if gameworld
-        .get_entities_by_type::<TetrisPiece>()
-        .is_empty()
-        && tetris_grid_timer > DROP_DOWN_SPEED
+    // the player's input for this tick, or the
+    //  recorded input when watching a replay
+    let tick = match gameworld
+        .replay
+        .next_played_tick()
     {
-        gameworld
-            .get_entities_by_type::<TetrisGrid>()
-            .first_mut()
-            .unwrap()
-            .gfx_time_elapsed = 0.;
-        let mut new_tetris_piece =
-            TetrisPiece::new();
-        // check losing condition if new piece is overlapping existing grid points
-        if is_piece_overlapping_grid_points(
-            gameworld,
-            &new_tetris_piece,
-        ) {
-            // if not overlapping an existing grid point, then lets set the graphics and insert the new piece
-            let activated_weapon = gameworld
-                .get_entities_by_type::<MegaMan>()
-                .first()
-                .unwrap()
-                .active_weapon
-                .clone();
-            new_tetris_piece
-                .set_piece_gfx(activated_weapon);
-            register_entities!(
-                gameworld,
-                new_tetris_piece
-            );
-        } else {
-            // set substate to failure sequence
-            let fail_sequence_state = Sequencer {
-                sequence:
-                    SequenceName::GamePlayDefeat(
-                        Defeat::Initialize,
-                    ),
-            };
-            register_entities!(
-                gameworld,
-                fail_sequence_state
-            );
-            return;
+        Some(tick) => tick,
+        None => {
+            let tick = replay::Tick { actions };
+            gameworld.replay.record(&tick);
+            tick
         }
-    }
-    // Input Switch Weapon
-    if key_pressed!(
-        gameworld.input,
-        KeyCode::F1,
-        KeyCode::Key1,
-        KeyCode::F2,
-        KeyCode::Key2,
-        KeyCode::F3,
-        KeyCode::Key3,
-        KeyCode::F4,
-        KeyCode::Key4,
-        KeyCode::F5,
-        KeyCode::Key5,
-        KeyCode::F6,
-        KeyCode::Key6,
-        KeyCode::F7,
-        KeyCode::Key7,
-        KeyCode::F8,
-        KeyCode::Key8,
-        KeyCode::F9,
-        KeyCode::Key9
-    ) {
-        let last_input = gameworld
-            .input
-            .keys_down
-            .iter()
-            .next()
-            .cloned();
-        let weapon: Option<NPCName> =
-            match last_input {
-                Some(KeyCode::F1)
-                | Some(KeyCode::Key1) => {
-                    Some(NPCName::BubbleMan)
-                }
-                Some(KeyCode::F2)
-                | Some(KeyCode::Key2) => {
-                    Some(NPCName::AirMan)
-                }
-                Some(KeyCode::F3)
-                | Some(KeyCode::Key3) => {
-                    Some(NPCName::QuickMan)
-                }
-                Some(KeyCode::F4)
-                | Some(KeyCode::Key4) => {
-                    Some(NPCName::HeatMan)
-                }
-                Some(KeyCode::F5)
-                | Some(KeyCode::Key5) => None,
-                Some(KeyCode::F6)
-                | Some(KeyCode::Key6) => {
-                    Some(NPCName::WoodMan)
-                }
-                Some(KeyCode::F7)
-                | Some(KeyCode::Key7) => {
-                    Some(NPCName::MetalMan)
-                }
-                Some(KeyCode::F8)
-                | Some(KeyCode::Key8) => {
-                    Some(NPCName::FlashMan)
-                }
-                Some(KeyCode::F9)
-                | Some(KeyCode::Key9) => {
-                    Some(NPCName::CrashMan)
-                }
-                _ => None,
-            };
+    };
+    // Switch Weapon
+    if let Some(weapon) = tick.switched_weapon() {
         let currently_active_weapon = gameworld
             .get_entities_by_type::<MegaMan>()
             .first()
@@ -438,347 +519,237 @@ async fn gameplay(gameworld: &mut GameWorld) {
                 .await;
         }
     }
-    // TODO: move input to individual function(s)
-    // Input Rotate Right (ClockWise)
-    if (gameworld.input.contains(&KeyCode::Up) || gameworld.input.contains(&KeyCode::Kp8)
-        || gameworld.input.contains(&KeyCode::Kp9) || gameworld.input.contains(&KeyCode::W)
-        || gameworld.input.contains(&KeyCode::E))
-        // TODO: update the tap gesture to detect taps within a region instead of a specific place
-        || gameworld.input.get_tap_location().is_some()
+    // speed up gravity as the fight goes on
+    let (robot_name, robot_hitpoints) = gameworld
+        .get_entities_by_type_and_property(
+            |npc: &NPC| npc.npc_state,
+            NPCState::Gameplay,
+        )
+        .first()
+        .map(|npc| (npc.name, npc.hitpoints))
+        .unwrap();
+    gameworld.puzzle.drop_down_speed =
+        gravity_curve(robot_name)
+            .drop_down_speed(
+                gameworld.puzzle.rows_cleared,
+                gameworld.puzzle.time_elapsed,
+                ROBOT_MAX_HITPOINTS
+                    .saturating_sub(
+                        robot_hitpoints,
+                    ),
+            );
+    // robots fight back, pushing garbage rows in
+    //  under the stack every so often
+    let (attack_interval, attack_rows) =
+        robot_attack(robot_name);
+    if robot_hitpoints > 0
+        && (gameworld.puzzle.time_elapsed
+            / attack_interval) as u32
+            > gameworld.puzzle.attacks_received
     {
-        let tetris_grid_reference = gameworld
-            .get_entities_by_type::<TetrisGrid>()
-            .first()
+        gameworld
+            .puzzle
+            .queue_garbage(attack_rows);
+        gameworld
+            .get_entities_by_type::<NPC>()
+            .iter_mut()
+            .find(|npc| {
+                npc.npc_state
+                    == NPCState::Gameplay
+            })
             .unwrap()
-            .matrix
-            .clone();
-        if let Some(active_tetris_piece) =
-            gameworld.get_entities_by_type::<TetrisPiece>().first_mut()
-        {
-            active_tetris_piece.rotate_clockwise(tetris_grid_reference);
+            .start_action(NPCAction::Attacking);
+    }
+    // and mess with the board using the gimmick
+    //  from their stage
+    let (gimmick_interval, hazard) =
+        robot_gimmick(
+            robot_name,
+            gameworld.puzzle.hazards_triggered,
+        );
+    let mut events = Vec::new();
+    if robot_hitpoints > 0
+        && (gameworld.puzzle.time_elapsed
+            / gimmick_interval) as u32
+            > gameworld.puzzle.hazards_triggered
+    {
+        gameworld
+            .puzzle
+            .trigger_hazard(hazard, &mut events);
+    }
+    // Weapon Ability
+    if tick
+        .actions
+        .contains(&replay::Action::UseAbility)
+    {
+        gameworld.puzzle.use_ability(&mut events);
+    }
+    // feed this tick's input to the puzzle rules
+    //  and react to what happened
+    events.extend(gameworld.puzzle.update(
+        &tick.puzzle_inputs(),
+        TICK_TIME,
+    ));
+    // the game timer runs on the puzzle's clock,
+    //  a replay being watched leaves the run's
+    //  time alone
+    let game_timer_start =
+        gameworld.simulation.game_timer_start;
+    let is_watching_replay = matches!(
+        gameworld.replay,
+        replay::Session::Playing { .. }
+    );
+    if let Some(game_timer) = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first_mut()
+        .filter(|_| !is_watching_replay)
+    {
+        game_timer.time_elapsed = game_timer_start
+            + gameworld.puzzle.time_elapsed;
+    }
+    // a t-spin is reported as the piece locks and
+    //  a chain as blocks land, both ahead of the
+    //  rows they flag
+    let mut t_spin = None;
+    let mut chain = 0;
+    for event in events {
+        match event {
+            puzzle::Event::TSpin(spin) => {
+                t_spin = Some(spin);
+            }
+            puzzle::Event::Chain(depth) => {
+                chain = depth;
+            }
+            puzzle::Event::PieceLocked => {
+                // show the combo and make it
+                //  sound bigger the longer it
+                //  goes
+                let combo =
+                    gameworld.puzzle.combo;
+                if let Some(combo_counter) =
+                    gameworld.get_entities_by_type::<ComboCounter>().first_mut()
+                {
+                    combo_counter.qty = combo;
+                    combo_counter.is_visible = combo >= MIN_SHOWN_COMBO;
+                }
+                if combo >= MIN_SHOWN_COMBO {
+                    gameworld
+                        .sfx_atlas
+                        .play(combo_sfx(combo))
+                        .await;
+                }
+                let active_weapon = gameworld
+                    .get_entities_by_type::<MegaMan>()
+                    .first()
+                    .unwrap()
+                    .active_weapon
+                    .clone();
+                gameworld
+                    .sfx_atlas
+                    .play(weapon_sfx(
+                        active_weapon,
+                    ))
+                    .await;
+            }
+            puzzle::Event::AbilityUsed(_) => {
+                let active_weapon = gameworld
+                    .get_entities_by_type::<MegaMan>()
+                    .first()
+                    .unwrap()
+                    .active_weapon
+                    .clone();
+                gameworld
+                    .sfx_atlas
+                    .play(weapon_sfx(
+                        active_weapon,
+                    ))
+                    .await;
+            }
+            puzzle::Event::RowsFlagged(rows) => {
+                damage_robot(
+                    gameworld, rows, t_spin,
+                    chain,
+                )
+                .await;
+            }
+            puzzle::Event::PerfectClear => {
+                perfect_clear(gameworld).await;
+            }
+            puzzle::Event::Hazard(_) => {
+                gameworld
+                    .get_entities_by_type::<NPC>()
+                    .iter_mut()
+                    .find(|npc| {
+                        npc.npc_state
+                            == NPCState::Gameplay
+                    })
+                    .unwrap()
+                    .start_action(
+                        NPCAction::Attacking,
+                    );
+            }
+            // block-out and lock-out both end the
+            //  fight
+            puzzle::Event::TopOut(_) => {
+                // set substate to failure sequence
+                let fail_sequence_state = Sequencer {
+                    sequence: SequenceName::GamePlayDefeat(Defeat::Initialize),
+                };
+                register_entities!(
+                    gameworld,
+                    fail_sequence_state
+                );
+            }
+            _ => {}
         }
     }
-    // Input Rotate Left
-    if gameworld.input.contains(&KeyCode::Kp7)
-        || gameworld.input.contains(&KeyCode::Q)
+    // once the flashing rows have been removed
+    //  the hit flash can stop
+    if gameworld.puzzle.grid_time_elapsed
+        >= puzzle::ROW_FLASH_TIME
+        && !gameworld.puzzle.has_flashing_rows()
     {
-        let tetris_grid_reference = gameworld
-            .get_entities_by_type::<TetrisGrid>()
-            .first()
-            .unwrap()
-            .matrix
-            .clone();
-        if let Some(active_tetris_piece) =
-            gameworld.get_entities_by_type::<TetrisPiece>().first_mut()
+        if let Some(hitflash) = gameworld
+            .get_entities_by_type::<HitFlash>()
+            .first_mut()
         {
-            active_tetris_piece.rotate_counter_clockwise(tetris_grid_reference);
+            hitflash.is_visible = false;
+            hitflash.is_active = false;
+            hitflash.is_full_screen = false;
         }
     }
-    // Touch Move (left or right)
-    // TODO: there is a lot of repeated code below that is duplicated with move left and move right input detection
-    // Also, the bool tuple is basically indicating if one of "move left", "move right", or "neither" are recorded.
-    // An enum would probably be more legible and idiomatic but seems like more code for limited improved
-    // readability.
-    let mut touch_move: (bool, bool) =
-        (false, false);
-    if gameworld.input.get_gestures().contains(
-        &input_manager::Gesture::TapHold,
-    ) {
-        // if let Some(tap_pos) = gameworld.input.get_tap_location().clone() {
-        if let Some(active_tetris_piece) =
-            gameworld.get_entities_by_type::<TetrisPiece>().first_mut()
-        {
-            if let Some(center_block_gfx_pos_x) = active_tetris_piece
-                .piece_type_shape
-                .iter()
-                .find(|gp| gp.point_state == TetrisGridPointState::Center)
-                .map(|gp| gp.gfx_position.x)
-            {
-                // let tap_x = tap_pos.0;
-
-                if let Some(last_touch) = touches().last() {
-                    let tap_x = last_touch.position.x;
-                    // move left
-                    if center_block_gfx_pos_x > tap_x {
-                        touch_move = (true, false);
-                    }
-
-                    // move right
-                    if tap_x > center_block_gfx_pos_x {
-                        touch_move = (false, true);
-                    }
-                }
-            }
-        }
-        // }
-    }
-    // Input Move Left
-    if (touch_move == (true, false)
-        || gameworld
-            .input
-            .contains(&KeyCode::Left)
-        || gameworld
-            .input
-            .contains(&KeyCode::Kp4)
-        || gameworld.input.contains(&KeyCode::A))
-        || gameworld
-            .input
-            .get_gestures()
-            .contains(
-            &input_manager::Gesture::SwipeLeft,
-        )
-    {
-        if gameworld
-            .entities
-            .iter()
-            .find(|e| {
-                e.as_any().is::<TetrisPiece>()
-            })
-            .is_none()
-        {
-            return;
-        };
-        let active_tetris_piece_grid_points =
-            gameworld
-                .entities
-                .iter()
-                .find(|e| {
-                    e.as_any().is::<TetrisPiece>()
-                })
-                .unwrap()
-                .as_any()
-                .downcast_ref::<TetrisPiece>()
-                .unwrap()
-                .piece_type_shape
-                .clone();
-        let left_most_grid_point =
-            active_tetris_piece_grid_points
-                .iter()
-                .map(|gp| {
-                    entities::idx_to_grid_point(
-                        gp.point_idx,
-                    )
-                    .x
-                })
-                .min()
-                .unwrap()
-                .clone();
-        // cant move left if active tetris piece is already at the far left
-        if left_most_grid_point > 0 {
-            let mut can_move = true;
-            let tetris_grid = gameworld
-                .entities
-                .iter_mut()
-                .find(|e| {
-                    e.as_any().is::<TetrisGrid>()
-                })
-                .unwrap()
-                .as_any_mut()
-                .downcast_mut::<TetrisGrid>()
-                .unwrap()
-                .clone();
-            // if _any_ grid point on the active tetris piece has a non-empty grid point to the left, then cannot move
-            for gp in
-                active_tetris_piece_grid_points
-                    .iter()
-            {
-                let test_idx = gp.point_idx - 1;
-                if tetris_grid.matrix
-                    [test_idx as usize]
-                    .point_state
-                    != TetrisGridPointState::Empty
-                {
-                    can_move = false;
-                }
-            }
-            if can_move {
-                gameworld
-                    .get_entities_by_type::<TetrisPiece>()
-                    .first_mut()
-                    .unwrap()
-                    .move_left();
-            }
-        }
-    };
-    // Input Right - Move Piece Right
-    if (touch_move == (false, true)
-        || gameworld
-            .input
-            .contains(&KeyCode::Right)
-        || gameworld
-            .input
-            .contains(&KeyCode::Kp6)
-        || gameworld.input.contains(&KeyCode::D))
+    sync_puzzle_entities(gameworld).await;
+    // the stage is decided, win or lose, so save
+    //  the replay and forget the suspended fight
+    if gameworld.puzzle.is_topped_out
         || gameworld
-            .input
-            .get_gestures()
-            .contains(
-            &input_manager::Gesture::SwipeRight,
-        )
-    {
-        if gameworld
-            .entities
-            .iter()
-            .find(|e| {
-                e.as_any().is::<TetrisPiece>()
-            })
-            .is_none()
-        {
-            return;
-        };
-        let active_tetris_piece_grid_points =
-            gameworld
-                .entities
-                .iter()
-                .find(|e| {
-                    e.as_any().is::<TetrisPiece>()
-                })
-                .unwrap()
-                .as_any()
-                .downcast_ref::<TetrisPiece>()
-                .unwrap()
-                .piece_type_shape
-                .clone();
-        let right_most_grid_point =
-            active_tetris_piece_grid_points
-                .iter()
-                .map(|gp| {
-                    entities::idx_to_grid_point(
-                        gp.point_idx,
-                    )
-                    .x
-                })
-                .max()
-                .unwrap()
-                .clone();
-        // cant move right if active tetris piece is already at the far right
-        if right_most_grid_point < 9 {
-            let mut can_move = true;
-            let tetris_grid = gameworld
-                .entities
-                .iter_mut()
-                .find(|e| {
-                    e.as_any().is::<TetrisGrid>()
-                })
-                .unwrap()
-                .as_any_mut()
-                .downcast_mut::<TetrisGrid>()
-                .unwrap()
-                .clone();
-            // if _any_ grid point on the active tetris piece has a non-empty grid point to the left, then cannot move
-            for gp in
-                active_tetris_piece_grid_points
-                    .iter()
-            {
-                let test_idx = gp.point_idx + 1;
-                if tetris_grid.matrix
-                    [test_idx as usize]
-                    .point_state
-                    != TetrisGridPointState::Empty
-                {
-                    can_move = false;
-                }
-            }
-            if can_move {
-                gameworld
-                    .get_entities_by_type::<TetrisPiece>()
-                    .first_mut()
-                    .unwrap()
-                    .move_right();
-            }
-        }
-    }
-    // Input Down - Move Piece Down
-    if gameworld.input.contains(&KeyCode::Down)
-        || gameworld.input.contains(&KeyCode::Kp2)
-        || gameworld.input.contains(&KeyCode::Kp5)
-        || gameworld.input.contains(&KeyCode::S)
-        || gameworld.input.contains(&KeyCode::X)
-    {
-        let tetris_grid_matrix_reference = gameworld
-            .get_entities_by_type::<TetrisGrid>()
+            .get_entities_by_type_and_property(
+                |npc: &NPC| npc.npc_state,
+                NPCState::Gameplay,
+            )
             .first()
             .unwrap()
-            .matrix
-            .clone();
-        if let Some(active_tetris_piece) =
-            gameworld.get_entities_by_type::<TetrisPiece>().first_mut()
-        {
-            let lowest_grid_point = active_tetris_piece
-                .piece_type_shape
-                .iter()
-                .map(|gp| entities::idx_to_grid_point(gp.point_idx).y)
-                .max()
-                .unwrap()
-                .clone();
-            // all grid points on the active tetris piece need to be eligible to move down on the tetris grid
-            if lowest_grid_point < 13
-                && active_tetris_piece.piece_type_shape.iter().all(|gp| {
-                    tetris_grid_matrix_reference[gp.point_idx as usize + 10].point_state
-                        == TetrisGridPointState::Empty
-                })
-            {
-                for gp in active_tetris_piece.piece_type_shape.iter_mut() {
-                    gp.point_idx += 10;
-                    gp.update_gfx_pos();
-                    // if you press down to accelerate the block, reset the drop down timer
-                    active_tetris_piece.turn_time_elapsed = 0.;
-                }
-            }
-        }
-    }
-    // Press Space to drop piece ultra fast
-    if get_keys_down().contains(&KeyCode::Space)
-        || get_keys_down().contains(&KeyCode::Kp0)
-        || gameworld
-            .input
-            .get_gestures()
-            .contains(&input_manager::Gesture::SwipeHoldDown)
+            .hitpoints
+            == 0
     {
-        if let Some(active_tetris_piece) =
-            gameworld.get_entities_by_type::<TetrisPiece>().first_mut()
-        {
-            if active_tetris_piece.turn_time_elapsed >= DROP_DOWN_CYCLE_TIME {
-                active_tetris_piece.turn_time_elapsed = DROP_DOWN_SPEED;
+        if !matches!(
+            gameworld.replay,
+            replay::Session::Playing { .. }
+        ) {
+            if let Err(err) = snapshot::Snapshot::discard(&mut snapshot::default_storage()) {
+                macroquad::logging::error!("failed to discard the suspended stage: {err}");
             }
         }
-    }
-    // No Input - set the new position of the active tetris piece
-    let tetris_grid_matrix_reference = gameworld
-        .get_entities_by_type::<TetrisGrid>()
-        .first()
-        .unwrap()
-        .matrix
-        .clone();
-    if let Some(active_tetris_piece) = gameworld
-        .get_entities_by_type::<TetrisPiece>()
-        .first_mut()
-    {
-        if active_tetris_piece.turn_time_elapsed
-            >= DROP_DOWN_SPEED
+        if let Err(err) = gameworld
+            .replay
+            .save(&mut replay::default_storage())
         {
-            let lowest_grid_point = active_tetris_piece
-                .piece_type_shape
-                .iter()
-                .map(|gp| entities::idx_to_grid_point(gp.point_idx).y)
-                .max()
-                .unwrap()
-                .clone();
-            if lowest_grid_point < 13
-                && active_tetris_piece.piece_type_shape.iter().all(|gp| {
-                    tetris_grid_matrix_reference[gp.point_idx as usize + 10].point_state
-                        == TetrisGridPointState::Empty
-                })
-            {
-                for gp in active_tetris_piece.piece_type_shape.iter_mut() {
-                    gp.point_idx += 10;
-                    gp.update_gfx_pos();
-                }
-                active_tetris_piece.turn_time_elapsed = 0.0;
-            }
+            macroquad::logging::error!(
+                "failed to save the replay: \
+                 {err}"
+            );
         }
     }
-    clear_lines(gameworld).await;
 }
 async fn run_wily_intro(
     gameworld: &mut GameWorld,
@@ -801,17 +772,10 @@ async fn run_wily_intro(
         SequenceName::DrWilyIntro(
             DrWilyIntro::Initialize,
         ) => {
-            if let Some(timer) = gameworld
-                .get_entities_by_type_and_property(
-                    |timer: &entities::Timer| timer.name.clone(),
-                    "intro_sequence".to_string(),
-                )
-                .first_mut()
-            {
-                if !timer.is_active {
-                    timer.is_active = true;
-                }
-            }
+            gameworld
+                .simulation
+                .intro_elapsed
+                .get_or_insert(0.);
             gameworld
                 .get_entities_by_type_and_property(
                     |sequencer: &Sequencer| {
@@ -828,13 +792,9 @@ async fn run_wily_intro(
             DrWilyIntro::WaitForReadyTimer,
         ) => {
             let timer_elapsed = gameworld
-                .get_entities_by_type_and_property(
-                    |timer: &entities::Timer| timer.name.clone(),
-                    "intro_sequence".to_string(),
-                )
-                .first()
-                .unwrap()
-                .elapsed_time;
+                .simulation
+                .intro_elapsed
+                .unwrap_or(0.);
             if timer_elapsed <= 3.0 {
                 return;
             }
@@ -1170,7 +1130,7 @@ async fn run_wily_intro(
                 .first_mut()
             {
                 let old_volume = track.params.volume.clone();
-                let new_volume = old_volume - (get_frame_time() / 6.);
+                let new_volume = old_volume - (TICK_TIME / 6.);
                 if new_volume <= 0. {
                     track.stop();
                     track.is_playing = false;
@@ -1181,7 +1141,7 @@ async fn run_wily_intro(
             }
             // Fade in the starfield by increasing the alpha of the blackfade
             if let Some(blackfade) = gameworld.get_entities_by_type::<BlackFade>().first_mut() {
-                blackfade.color.a -= 0.15 * get_frame_time();
+                blackfade.color.a -= 0.15 * TICK_TIME;
             }
             match gameworld
                 .get_entities_by_type_and_property(
@@ -1213,18 +1173,11 @@ async fn run_wily_intro(
             DrWilyIntro::FillLifeBar,
         ) => {
             // make life bar visible
-            let (life_meter_box_is_visible, life_meter_box_qty, life_meter_box_frame_elapsed) =
-                gameworld
-                    .get_entities_by_type::<LifeMeterBox>()
-                    .first_mut()
-                    .map(|life_meter_box| {
-                        (
-                            life_meter_box.is_visible,
-                            life_meter_box.qty,
-                            life_meter_box.frame_elapsed,
-                        )
-                    })
-                    .unwrap();
+            let (life_meter_box_is_visible, life_meter_box_qty) = gameworld
+                .get_entities_by_type::<LifeMeterBox>()
+                .first_mut()
+                .map(|life_meter_box| (life_meter_box.is_visible, life_meter_box.qty))
+                .unwrap();
             if !life_meter_box_is_visible {
                 gameworld
                     .get_entities_by_type::<LifeMeterBox>()
@@ -1235,22 +1188,30 @@ async fn run_wily_intro(
                     sfx::SFXName::LifeMeterFill,
                 );
             }
-            if life_meter_box_frame_elapsed
+            let robot_hitpoints = gameworld
+                .get_entities_by_type_and_property(|npc: &NPC| npc.npc_state, NPCState::Gameplay)
+                .first()
+                .unwrap()
+                .hitpoints;
+            if gameworld
+                .simulation
+                .life_meter_elapsed
                 >= 0.09
-                && life_meter_box_qty < 16
+                && life_meter_box_qty
+                    < robot_hitpoints
             {
                 gameworld
-                    .get_entities_by_type::<LifeMeterBox>()
-                    .first_mut()
-                    .unwrap()
-                    .frame_elapsed = 0.;
+                    .simulation
+                    .life_meter_elapsed = 0.;
                 gameworld
                     .get_entities_by_type::<LifeMeterBox>()
                     .first_mut()
                     .unwrap()
                     .qty += 1;
             }
-            if life_meter_box_qty == 16 {
+            if life_meter_box_qty
+                == robot_hitpoints
+            {
                 gameworld.sfx_atlas.stop(
                     sfx::SFXName::LifeMeterFill,
                 );
@@ -1299,32 +1260,20 @@ async fn run_wily_intro(
 async fn run_stage_intro(
     gameworld: &mut GameWorld,
 ) {
-    if let Some(timer) = gameworld
-        .get_entities_by_type_and_property(
-            |timer: &entities::Timer| {
-                timer.name.clone()
-            },
-            "intro_sequence".to_string(),
-        )
-        .first_mut()
+    if gameworld
+        .simulation
+        .intro_elapsed
+        .is_none()
     {
-        if !timer.is_active {
-            timer.elapsed_time = 0.;
-            timer.is_active = true;
-            return;
-        }
+        gameworld.simulation.intro_elapsed =
+            Some(0.);
+        return;
     }
     // step 1: flash ready until 3 seconds have passed
     let timer_elapsed = gameworld
-        .get_entities_by_type_and_property(
-            |timer: &entities::Timer| {
-                timer.name.clone()
-            },
-            "intro_sequence".to_string(),
-        )
-        .first()
-        .unwrap()
-        .elapsed_time;
+        .simulation
+        .intro_elapsed
+        .unwrap_or(0.);
     if timer_elapsed <= 3.0 {
         return;
     }
@@ -1369,13 +1318,16 @@ async fn run_stage_intro(
         megaman::Action::WarpingIn => {
             let y_warp_threshold = {
                 match gameworld
-                    .get_entities_by_type_and_property(|npc: &NPC| npc.npc_state, NPCState::Gameplay)
+                    .get_entities_by_type_and_property(
+                        |npc: &NPC| npc.npc_state,
+                        NPCState::Gameplay,
+                    )
                     .first()
                     .unwrap()
-                    .name {
-                NPCName::RustMan => 25.,
-                _ => 18.
-
+                    .name
+                {
+                    NPCName::RustMan => 25.,
+                    _ => 18.,
                 }
             };
             if megaman_position_y
@@ -1465,7 +1417,6 @@ async fn run_stage_intro(
     let (
         life_meter_box_is_visible,
         life_meter_box_qty,
-        life_meter_box_frame_elapsed,
     ) = gameworld
         .get_entities_by_type::<LifeMeterBox>()
         .first_mut()
@@ -1473,7 +1424,6 @@ async fn run_stage_intro(
             (
                 life_meter_box.is_visible,
                 life_meter_box.qty,
-                life_meter_box.frame_elapsed,
             )
         })
         .unwrap();
@@ -1488,17 +1438,26 @@ async fn run_stage_intro(
             sfx::SFXName::LifeMeterFill,
         );
     }
-    // fill life bar and add life to the robot's hp
-    if life_meter_box_frame_elapsed >= 0.09
-        && life_meter_box_qty < 16
+    // fill life bar and add life to the robot's
+    //  hp, short of full when a suspended stage
+    //  is resumed
+    let robot_hitpoints = gameworld
+        .get_entities_by_type_and_property(
+            |npc: &NPC| npc.npc_state,
+            NPCState::Gameplay,
+        )
+        .first()
+        .unwrap()
+        .hitpoints;
+    if gameworld.simulation.life_meter_elapsed
+        >= 0.09
+        && life_meter_box_qty < robot_hitpoints
     {
-        // the robot is instantiated with 16 life so we only need to add to the lifebar here
-        gameworld
-            .get_entities_by_type::<LifeMeterBox>(
-            )
-            .first_mut()
-            .unwrap()
-            .frame_elapsed = 0.;
+        // the robot already has its hitpoints so
+        //  we only need to add to the lifebar
+        //  here
+        gameworld.simulation.life_meter_elapsed =
+            0.;
         gameworld
             .get_entities_by_type::<LifeMeterBox>(
             )
@@ -1506,7 +1465,7 @@ async fn run_stage_intro(
             .unwrap()
             .qty += 1;
     }
-    if life_meter_box_qty == 16 {
+    if life_meter_box_qty == robot_hitpoints {
         gameworld
             .sfx_atlas
             .stop(sfx::SFXName::LifeMeterFill);
@@ -1530,10 +1489,16 @@ async fn initialize(gameworld: &mut GameWorld) {
     // gameworld.loaded_textures.clear();
     //gameworld.sfx_atlas.clear();
     // MUST LOAD GFX
-    // we use the cursor position from stage select to determine the robot we are facing off against
+    // we use the cursor position from stage
+    //  select to determine the robot we are
+    //  facing off against. a replay started from
+    //  the defeat screen skips stage select, and
+    //  brings its own robot
     let cursor_idx = {
         let cursors = gameworld.get_entities_by_type::<entities::StageSelectCursor>();
-        cursors.first().unwrap().index
+        cursors
+            .first()
+            .map_or(0, |cursor| cursor.index)
     };
     // CLEAR ENTITIES LIST
     // retain the robots and game timer but clear the remaining entities so that when we switch game states we dont lose the data
@@ -1541,7 +1506,10 @@ async fn initialize(gameworld: &mut GameWorld) {
         entity.as_any().is::<NPC>()
             || entity.as_any().is::<GameTimer>()
     });
-    // get the robot name to initialize the correct stage
+    // a replay picked to be watched decides the
+    //  stage, otherwise get the robot name from
+    //  the stage select
+    let playback = gameworld.next_replay.take();
     let robot_name = match cursor_idx {
         1 => npc::NPCName::BubbleMan,
         2 => npc::NPCName::AirMan,
@@ -1554,6 +1522,36 @@ async fn initialize(gameworld: &mut GameWorld) {
         9 => npc::NPCName::CrashMan,
         10 | _ => npc::NPCName::RustMan,
     };
+    // a fight against the picked robot that was
+    //  suspended when the window or tab closed
+    //  picks back up. the snapshot is only kept
+    //  in storage, so picking the robot again is
+    //  how the player continues
+    let resume = if playback.is_some() {
+        None
+    } else {
+        match snapshot::Snapshot::load(
+            &snapshot::default_storage(),
+        ) {
+            Ok(snapshot) => {
+                snapshot.filter(|snapshot| {
+                    snapshot.robot == robot_name
+                })
+            }
+            Err(err) => {
+                macroquad::logging::error!(
+                    "failed to read the \
+                     suspended stage: {err}"
+                );
+                None
+            }
+        }
+    };
+    let robot_name = playback
+        .as_ref()
+        .map_or(robot_name, |replay| {
+            replay.robot
+        });
     // set which robot is active - all others inactive
     gameworld
         .get_entities_by_type::<npc::NPC>()
@@ -1651,6 +1649,8 @@ async fn initialize(gameworld: &mut GameWorld) {
     // load weapon box select
     let weapon_box_select =
         entities::WeaponBoxSelect::new().await;
+    // load hold box
+    let hold_box = entities::HoldBox::new().await;
     // load READY gameplay
     let mut ready_entity =
         entities::GPReady::new().await;
@@ -1663,9 +1663,16 @@ async fn initialize(gameworld: &mut GameWorld) {
         &background_entity,
         &life_meter_box,
         &weapon_box_select,
+        &hold_box,
         &ready_entity
     );
-    register_entities!(gameworld, hitflash);
+    // load combo counter
+    let combo_counter = ComboCounter::new().await;
+    register_entities!(
+        gameworld,
+        hitflash,
+        combo_counter
+    );
     load_animations!(
         gameworld,
         gfx::Name::STARlarge,
@@ -1689,10 +1696,6 @@ async fn initialize(gameworld: &mut GameWorld) {
     megaman.action = megaman::Action::Nothing;
     megaman.load_all_assets(gameworld).await;
     megaman.position = vec2(16., -31.);
-    // create an intro sequence timer so we know if the READY sequence has flashed long enough
-    let timer =
-        entities::Timer::new("intro_sequence")
-            .await;
     // create the game timer for best times if not carrying one over
     if gameworld
         .get_entities_by_type::<GameTimer>()
@@ -1708,14 +1711,133 @@ async fn initialize(gameworld: &mut GameWorld) {
             .unwrap()
             .is_visible = true;
     }
-    // create the tetris grid and pieces
+    // create the tetris grid and pieces, sized by
+    //  the puzzle settings so "big" and narrow
+    //  boards share the same gameplay code
+    let settings = playback
+        .as_ref()
+        .map(|replay| replay.settings.clone())
+        .or(resume.as_ref().map(|snapshot| {
+            snapshot.settings.clone()
+        }))
+        .unwrap_or_else(|| {
+            load_options().settings()
+        });
     let tetris_grid =
-        resources::entities::TetrisGrid::new();
+        resources::entities::TetrisGrid::new(
+            settings.dimensions.width,
+            settings.dimensions.height,
+        );
+    // the puzzle rules start over for every
+    //  stage, the grid and piece entities just
+    //  draw them. a replay or suspended stage
+    //  brings its own seed, otherwise roll a new
+    //  one. either way it gets recorded on the
+    //  gameworld and in the replay so the stage
+    //  can be reproduced later
+    gameworld.piece_seed =
+        match (&playback, &resume) {
+            (Some(replay), _) => replay.seed,
+            (None, Some(snapshot)) => {
+                snapshot.seed
+            }
+            (None, None) => {
+                macroquad::rand::rand() as u64
+            }
+        };
     // LOAD DATA files
-    //  none.
-    // load conditional misc music
-    let defeat_music = music::MusicTrack::load(
-        music::TrackName::Defeat,
+    //  the pieces for the stage, the standard
+    //   tetrominoes unless the stage has its own
+    //   set. a set that can't be loaded is logged
+    //   and the stage falls back on the standard
+    //   tetrominoes
+    let piece_set =
+        match piece_set_file(robot_name) {
+            Some(path) => load_piece_set(path)
+                .await
+                .unwrap_or_else(|err| {
+                    macroquad::logging::error!(
+                        "failed to load piece \
+                         set {path}: {err}"
+                    );
+                    puzzle::PieceSet::default()
+                }),
+            None => puzzle::PieceSet::default(),
+        };
+    // a suspended stage picks up where it left
+    //  off. a snapshot whose puzzle state no
+    //  longer fits the stage's piece set is
+    //  dropped and the stage starts over
+    let resume = resume
+        .filter(|_| playback.is_none())
+        .and_then(|snapshot| {
+            let puzzle = snapshot
+                .restore_puzzle(piece_set.clone())
+                .ok()?;
+            Some((snapshot, puzzle))
+        });
+    let resumed_snapshot = match resume {
+        Some((snapshot, puzzle)) => {
+            gameworld.puzzle = puzzle;
+            Some(snapshot)
+        }
+        None => {
+            gameworld.puzzle =
+                puzzle::Engine::new(
+                    puzzle::Settings {
+                        piece_set,
+                        ..settings.clone()
+                    },
+                    gameworld.piece_seed,
+                );
+            None
+        }
+    };
+    // the stage's clock starts from nothing,
+    //  rather than catching up on the time spent
+    //  loading, and the game timer carries on
+    //  from the stages before
+    let game_timer_start = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first()
+        .map_or(0., |game_timer| {
+            game_timer.time_elapsed
+        });
+    gameworld.simulation = Simulation {
+        game_timer_start,
+        suspended_at: gameworld
+            .puzzle
+            .time_elapsed,
+        ..Simulation::default()
+    };
+    // record the stage so it can be played back,
+    //  unless it's a replay being watched. a
+    //  resumed stage didn't play out from its
+    //  seed so it can't be recorded
+    gameworld.replay =
+        match (playback, &resumed_snapshot) {
+            (Some(replay), _) => {
+                replay::Session::Playing {
+                    replay,
+                    tick_idx: 0,
+                }
+            }
+            (None, Some(_)) => {
+                replay::Session::Finished
+            }
+            (None, None) => {
+                replay::Session::Recording(
+                    replay::Replay::new(
+                        gameworld.piece_seed,
+                        robot_name,
+                        settings,
+                    ),
+                )
+            }
+        };
+    // load conditional misc music
+    let defeat_music = music::MusicTrack::load(
+        music::TrackName::Defeat,
     )
     .await;
     let victory_music = music::MusicTrack::load(
@@ -1901,7 +2023,6 @@ async fn initialize(gameworld: &mut GameWorld) {
         ready_entity,
         life_meter_box,
         megaman,
-        timer,
         tetris_grid
     );
     // because the npc weapon select graphics carry over from previous stages we need to insert
@@ -1909,6 +2030,9 @@ async fn initialize(gameworld: &mut GameWorld) {
     gameworld
         .entities
         .insert(1, Box::new(weapon_box_select));
+    gameworld
+        .entities
+        .insert(1, Box::new(hold_box));
     if robot_name == NPCName::RustMan {
         gameworld
             .get_entities_by_type_and_property(
@@ -1919,9 +2043,135 @@ async fn initialize(gameworld: &mut GameWorld) {
             .unwrap()
             .npc_avatar_pos += 8.;
     }
+    if let Some(snapshot) = resumed_snapshot {
+        resume_stage(gameworld, &snapshot);
+    }
     // SET INIT = TRUE
     gameworld.gamestate.is_initialized = true;
 }
+/// put back everything a [snapshot::Snapshot]
+///  saved outside the puzzle rules, once
+///  [initialize()] has set up the stage's
+///  entities. the life meter still fills during
+///  the intro, up to the robot's saved hitpoints
+fn resume_stage(
+    gameworld: &mut GameWorld,
+    snapshot: &snapshot::Snapshot,
+) {
+    for robot in gameworld
+        .get_entities_by_type::<NPC>()
+        .iter_mut()
+    {
+        robot.is_defeated = snapshot
+            .defeated_robots
+            .contains(&robot.name);
+        if robot.name == snapshot.robot {
+            robot.hitpoints = snapshot.hitpoints;
+        }
+    }
+    let weapon = snapshot.weapon;
+    gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first_mut()
+        .unwrap()
+        .switch_active_weapon(weapon);
+    gameworld
+        .get_entities_by_type::<WeaponBoxSelect>()
+        .first_mut()
+        .unwrap()
+        .select(weapon);
+    gameworld
+        .get_entities_by_type::<TetrisGrid>()
+        .first_mut()
+        .unwrap()
+        .matrix
+        .iter_mut()
+        .for_each(|gp| {
+            gp.switch_grid_point_gfx(weapon)
+        });
+    if let Some(game_timer) = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first_mut()
+    {
+        game_timer.time_elapsed =
+            snapshot.game_timer;
+    }
+    gameworld.simulation.game_timer_start =
+        snapshot.game_timer
+            - gameworld.puzzle.time_elapsed;
+}
+/// save the fight in progress to
+///  [snapshot::SNAPSHOT_FILE], so it can be
+///  picked back up after the window or tab is
+///  closed. nothing is saved during the intro,
+///  once the fight is over or while watching a
+///  replay
+fn suspend(gameworld: &mut GameWorld) {
+    if !gameworld.gamestate.is_initialized
+        || gameworld.puzzle.is_topped_out
+        || matches!(
+            gameworld.replay,
+            replay::Session::Playing { .. }
+        )
+        || !gameworld
+            .get_entities_by_type::<GPReady>()
+            .is_empty()
+    {
+        return;
+    }
+    let Some((robot, hitpoints)) = gameworld
+        .get_entities_by_type_and_property(
+            |npc: &NPC| npc.npc_state,
+            NPCState::Gameplay,
+        )
+        .first()
+        .map(|npc| (npc.name, npc.hitpoints))
+    else {
+        return;
+    };
+    if hitpoints == 0 {
+        return;
+    }
+    let weapon = gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first()
+        .unwrap()
+        .active_weapon;
+    let defeated_robots = gameworld
+        .get_entities_by_type::<NPC>()
+        .iter()
+        .filter(|npc| npc.is_defeated)
+        .map(|npc| npc.name)
+        .collect();
+    let game_timer = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first()
+        .map_or(0., |game_timer| {
+            game_timer.time_elapsed
+        });
+    let snapshot = snapshot::Snapshot {
+        seed: gameworld.piece_seed,
+        robot,
+        hitpoints,
+        weapon,
+        defeated_robots,
+        game_timer,
+        settings: gameworld
+            .puzzle
+            .settings
+            .clone(),
+        puzzle_state: gameworld
+            .puzzle
+            .save_state(),
+    };
+    if let Err(err) = snapshot
+        .save(&mut snapshot::default_storage())
+    {
+        macroquad::logging::error!(
+            "failed to suspend the stage: {err}"
+        );
+    }
+}
 /// VICTORY - Run Sequence when robot is defeated
 /// 0. stop music, stop clock
 /// 1. robot explodes, sfx plays
@@ -2124,6 +2374,11 @@ async fn run_robot_defeated(
             .name
             == NPCName::DrWily
         {
+            let victory_music_elapsed = gameworld
+                .simulation
+                .music_elapsed(
+                    &TrackName::WilyVictory,
+                );
             if let Some(victory_music_track) = gameworld
                 .get_entities_by_type_and_property(
                     |track: &MusicTrack| track.name.clone(),
@@ -2131,11 +2386,11 @@ async fn run_robot_defeated(
                 )
                 .first_mut()
             {
-                if victory_music_track.elapsed == 0. {
+                if victory_music_elapsed == 0. {
                     victory_music_track.play();
                 }
 
-                if victory_music_track.elapsed >= 10.0 {
+                if victory_music_elapsed >= 10.0 {
                     victory_music_track.stop();
                     gameworld
                         .get_entities_by_type_and_property(
@@ -2150,6 +2405,11 @@ async fn run_robot_defeated(
         }
         // else do normal robot stuff
         else {
+            let victory_music_elapsed = gameworld
+                .simulation
+                .music_elapsed(
+                    &TrackName::RobotVictory,
+                );
             if let Some(victory_music_track) = gameworld
                 .get_entities_by_type_and_property(
                     |track: &MusicTrack| track.name.clone(),
@@ -2157,11 +2417,11 @@ async fn run_robot_defeated(
                 )
                 .first_mut()
             {
-                if victory_music_track.elapsed == 0. {
+                if victory_music_elapsed == 0. {
                     victory_music_track.play();
                 }
 
-                if victory_music_track.elapsed >= 5.0 {
+                if victory_music_elapsed >= 5.0 {
                     victory_music_track.stop();
                     gameworld
                         .get_entities_by_type_and_property(
@@ -2219,7 +2479,7 @@ async fn run_robot_defeated(
                 .name
                 == NPCName::DrWily
             {
-                let fade_qty = get_frame_time() / 3.;
+                let fade_qty = TICK_TIME / 3.;
 
                 // fade stage
                 gameworld
@@ -2242,6 +2502,11 @@ async fn run_robot_defeated(
                     .first_mut()
                     .unwrap()
                     .alpha -= fade_qty;
+                gameworld
+                    .get_entities_by_type::<HoldBox>()
+                    .first_mut()
+                    .unwrap()
+                    .alpha -= fade_qty;
 
                 // fade the mega man face
                 gameworld
@@ -2471,6 +2736,9 @@ async fn fail_sequence_wait_for_orbs(
 async fn fail_sequence_play_music(
     gameworld: &mut GameWorld,
 ) -> SequenceName {
+    let fail_music_elapsed = gameworld
+        .simulation
+        .music_elapsed(&TrackName::Defeat);
     if let Some(fail_music_track) = gameworld
         .get_entities_by_type_and_property(
             |track: &MusicTrack| {
@@ -2480,14 +2748,14 @@ async fn fail_sequence_play_music(
         )
         .first_mut()
     {
-        if fail_music_track.elapsed == 0. {
+        if fail_music_elapsed == 0. {
             fail_music_track.play();
         }
         // Fail music last just 3.0 seconds
-        if fail_music_track.elapsed >= 3.0 {
+        if fail_music_elapsed >= 3.0 {
             fail_music_track.stop();
             SequenceName::GamePlayDefeat(
-                Defeat::EndSequence,
+                Defeat::ShowPassword,
             )
         } else {
             SequenceName::GamePlayDefeat(
@@ -2500,6 +2768,83 @@ async fn fail_sequence_play_music(
         )
     }
 }
+/// show the password for the run so far until the
+///  player moves on, so they can pick it back up
+///  later. R watches the lost fight played back
+///  from its replay instead
+async fn fail_sequence_show_password(
+    gameworld: &mut GameWorld,
+) -> SequenceName {
+    if gameworld
+        .get_entities_by_type::<PasswordGrid>()
+        .is_empty()
+    {
+        let progress =
+            campaign_progress(gameworld);
+        let mut password_grid =
+            PasswordGrid::new().await;
+        password_grid.dots =
+            password::Password::encode(&progress)
+                .dots;
+        password_grid.is_visible = true;
+        register_entities!(
+            gameworld,
+            password_grid
+        );
+        return SequenceName::GamePlayDefeat(
+            Defeat::ShowPassword,
+        );
+    }
+    // the replay was saved as the fight was lost,
+    //  the stage starts over to play it once the
+    //  sequence ends
+    if key_pressed!(gameworld.input, KeyCode::R) {
+        match replay::Replay::load(
+            &replay::default_storage(),
+        ) {
+            Ok(Some(replay)) => {
+                gameworld.next_replay =
+                    Some(replay);
+                gameworld.entities.retain(
+                    |entity| {
+                        !entity
+                            .as_any()
+                            .is::<PasswordGrid>()
+                    },
+                );
+                return SequenceName::GamePlayDefeat(Defeat::EndSequence);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                macroquad::logging::error!(
+                    "failed to load the replay: \
+                     {err}"
+                );
+            }
+        }
+    }
+    if key_pressed!(
+        gameworld.input,
+        KeyCode::Enter,
+        KeyCode::KpEnter,
+        KeyCode::Space
+    ) || gameworld
+        .input
+        .get_tap_location()
+        .is_some()
+    {
+        gameworld.entities.retain(|entity| {
+            !entity.as_any().is::<PasswordGrid>()
+        });
+        SequenceName::GamePlayDefeat(
+            Defeat::EndSequence,
+        )
+    } else {
+        SequenceName::GamePlayDefeat(
+            Defeat::ShowPassword,
+        )
+    }
+}
 async fn run_fail_sequence(
     gameworld: &mut GameWorld,
 ) -> GameState {
@@ -2576,10 +2921,28 @@ async fn run_fail_sequence(
                 )
                 .await
             }
+            SequenceName::GamePlayDefeat(
+                Defeat::ShowPassword,
+            ) => {
+                fail_sequence_show_password(
+                    gameworld,
+                )
+                .await
+            }
             SequenceName::GamePlayDefeat(
                 Defeat::EndSequence,
             )
             | _ => {
+                // start the stage over to watch
+                //  the replay picked on the
+                //  password screen
+                if gameworld.next_replay.is_some()
+                {
+                    return GameState {
+                        state: State::Gameplay,
+                        is_initialized: false,
+                    };
+                }
                 return GameState {
                     state: State::StageSelect,
                     is_initialized: false,
@@ -2609,6 +2972,236 @@ async fn run_fail_sequence(
         is_initialized: true,
     };
 }
+// PASSWORD ENTRY
+/// stage select's way into the password entry
+///  screen, pressing P. stage select calls this
+///  every frame and moves to the returned state
+pub fn read_password_entry(
+    gameworld: &mut GameWorld,
+) -> Option<GameState> {
+    key_pressed!(gameworld.input, KeyCode::P)
+        .then_some(GameState {
+            state: State::PasswordEntry,
+            is_initialized: false,
+        })
+}
+/// the password entry screen, reached from stage
+///  select with [read_password_entry]: move the
+///  cursor around the grid with the arrow keys,
+///  toggle a dot with space and press enter to
+///  carry on the run the password was given for.
+///  escape goes back without changing anything
+pub async fn run_password_entry(
+    gameworld: &mut GameWorld,
+) -> GameState {
+    if !gameworld.gamestate.is_initialized {
+        // retain the robots and game timer, the
+        //  password is written onto them
+        gameworld.entities.retain(|entity| {
+            entity.as_any().is::<NPC>()
+                || entity
+                    .as_any()
+                    .is::<GameTimer>()
+        });
+        let mut password_grid =
+            PasswordGrid::new().await;
+        password_grid.cursor = Some((0, 0));
+        password_grid.is_visible = true;
+        register_entities!(
+            gameworld,
+            password_grid
+        );
+        return password_entry_state(
+            &password::EntryOutcome::Editing,
+        );
+    }
+    let key = read_password_entry_key(gameworld);
+    let Some(password_grid) = gameworld
+        .get_entities_by_type::<PasswordGrid>()
+        .into_iter()
+        .next()
+    else {
+        return password_entry_state(
+            &password::EntryOutcome::Editing,
+        );
+    };
+    let Some(key) = key else {
+        return password_entry_state(
+            &password::EntryOutcome::Editing,
+        );
+    };
+    let mut entry = password::Entry {
+        password: password::Password {
+            dots: password_grid.dots,
+        },
+        cursor: password_grid
+            .cursor
+            .unwrap_or((0, 0)),
+    };
+    let outcome = entry.press(key);
+    password_grid.dots = entry.password.dots;
+    password_grid.cursor = Some(entry.cursor);
+    match &outcome {
+        password::EntryOutcome::Refused(_) => {
+            password_grid.is_error = true
+        }
+        _ if key
+            == password::EntryKey::Toggle =>
+        {
+            password_grid.is_error = false
+        }
+        _ => {}
+    }
+    if let password::EntryOutcome::Accepted(
+        progress,
+    ) = &outcome
+    {
+        restore_progress(gameworld, progress)
+            .await;
+    }
+    password_entry_state(&outcome)
+}
+/// the key pressed on the password entry screen
+///  this frame, if any
+fn read_password_entry_key(
+    gameworld: &mut GameWorld,
+) -> Option<password::EntryKey> {
+    if key_pressed!(
+        gameworld.input,
+        KeyCode::Escape
+    ) {
+        Some(password::EntryKey::Back)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Enter,
+        KeyCode::KpEnter
+    ) {
+        Some(password::EntryKey::Submit)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Space,
+        KeyCode::X
+    ) {
+        Some(password::EntryKey::Toggle)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Up,
+        KeyCode::W
+    ) {
+        Some(password::EntryKey::Up)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Down,
+        KeyCode::S
+    ) {
+        Some(password::EntryKey::Down)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Left,
+        KeyCode::A
+    ) {
+        Some(password::EntryKey::Left)
+    } else if key_pressed!(
+        gameworld.input,
+        KeyCode::Right,
+        KeyCode::D
+    ) {
+        Some(password::EntryKey::Right)
+    } else {
+        None
+    }
+}
+/// the password entry screen stays up until a
+///  password is accepted or the player backs out,
+///  both of which go back to stage select to pick
+///  the next robot
+fn password_entry_state(
+    outcome: &password::EntryOutcome,
+) -> GameState {
+    match outcome {
+        password::EntryOutcome::Editing
+        | password::EntryOutcome::Refused(_) => {
+            GameState {
+                state: State::PasswordEntry,
+                is_initialized: true,
+            }
+        }
+        password::EntryOutcome::Accepted(_)
+        | password::EntryOutcome::Cancelled => {
+            GameState {
+                state: State::StageSelect,
+                is_initialized: false,
+            }
+        }
+    }
+}
+/// what a password keeps of the run so far: the
+///  robot masters beaten and the game timer's
+///  bucket
+fn campaign_progress(
+    gameworld: &mut GameWorld,
+) -> password::Progress {
+    let defeated: Vec<NPCName> = gameworld
+        .get_entities_by_type::<NPC>()
+        .iter()
+        .filter(|npc| npc.is_defeated)
+        .map(|npc| npc.name)
+        .collect();
+    let game_time = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first()
+        .map_or(0., |game_timer| {
+            game_timer.time_elapsed
+        });
+    password::Progress {
+        defeated_robots: ROBOT_MASTERS
+            .into_iter()
+            .filter(|robot| {
+                defeated.contains(robot)
+            })
+            .collect(),
+        timer_bucket: password::timer_bucket(
+            game_time,
+        ),
+    }
+}
+/// mark the robot masters beaten in a password's
+///  run as defeated, so stage select and the
+///  weapon menu pick them up, and start the game
+///  timer from the start of the password's bucket
+async fn restore_progress(
+    gameworld: &mut GameWorld,
+    progress: &password::Progress,
+) {
+    for robot in gameworld
+        .get_entities_by_type::<NPC>()
+        .iter_mut()
+        .filter(|npc| {
+            ROBOT_MASTERS.contains(&npc.name)
+        })
+    {
+        let is_defeated = progress
+            .defeated_robots
+            .contains(&robot.name);
+        robot.is_defeated = is_defeated;
+        robot.gp_activated = is_defeated;
+    }
+    if gameworld
+        .get_entities_by_type::<GameTimer>()
+        .is_empty()
+    {
+        let game_timer =
+            entities::GameTimer::new().await;
+        register_entities!(gameworld, game_timer);
+    }
+    gameworld
+        .get_entities_by_type::<GameTimer>()
+        .first_mut()
+        .unwrap()
+        .time_elapsed = progress.timer_bucket
+        as f32
+        * password::TIMER_BUCKET_SECONDS;
+}
 // HELPER FUNCTIONS
 pub async fn switch_weapon(
     gameworld: &mut GameWorld,
@@ -2650,485 +3243,1256 @@ pub async fn switch_weapon(
         .for_each(|gp| {
             gp.switch_grid_point_gfx(weapon)
         });
-    if gameworld
+    // cleared rows charge the selected weapon's
+    //  ability
+    gameworld.puzzle.selected_ability =
+        weapon.and_then(weapon_ability);
+    // re-skin the active, upcoming and held
+    //  pieces
+    gameworld
         .get_entities_by_type::<TetrisPiece>()
         .iter_mut()
-        .next()
-        .is_some()
-    {
-        gameworld
-            .get_entities_by_type::<TetrisPiece>()
-            .iter_mut()
-            .next()
-            .unwrap()
-            .piece_type_shape
-            .iter_mut()
-            .for_each(|gp| {
-                gp.switch_grid_point_gfx(weapon)
-            });
-    }
+        .for_each(|tetris_piece| {
+            tetris_piece
+                .piece_type_shape
+                .iter_mut()
+                .for_each(|gp| {
+                    gp.switch_grid_point_gfx(
+                        weapon,
+                    )
+                });
+        });
 }
-/// find lines to clear on the tetris grid, make them flash, find flashing lines, make them empty, move supported blocks down, do damage
-async fn clear_lines(gameworld: &mut GameWorld) {
-    let mut new_rows_cleared: u8 = 0;
-    // find lines to clear and make them flash
-    if let Some(tetris_grid) = gameworld
-        .get_entities_by_type::<TetrisGrid>()
-        .first_mut()
-    {
-        tetris_grid
-            .matrix
-            .chunks_mut(10)
-            .filter(|row| {
-                row.iter()
-                    .all(|gp| gp.point_state == TetrisGridPointState::Active)
-            })
-            .for_each(|row| {
-                new_rows_cleared += 1;
-                row.iter_mut().rev().for_each(|gp| {
-                    gp.switch_grid_point_state(TetrisGridPointState::Flashing);
-                })
-            });
-        // reset timer
-        if new_rows_cleared > 0 {
-            tetris_grid.gfx_time_elapsed = 0.;
+/// the player's options for a new stage. the
+///  options are saved with the defaults when
+///  there aren't any yet, so there's a file to
+///  edit. options that can't be read are logged
+///  and the defaults used
+fn load_options() -> options::Options {
+    let mut storage = options::default_storage();
+    match options::Options::load(&storage) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            let options =
+                options::Options::default();
+            if let Err(err) =
+                options.save(&mut storage)
+            {
+                macroquad::logging::error!(
+                    "failed to save the \
+                     options: {err}"
+                );
+            }
+            options
+        }
+        Err(err) => {
+            macroquad::logging::error!(
+                "failed to read the options: \
+                 {err}"
+            );
+            options::Options::default()
         }
     }
-    // find the bottom most flashing line, make the grid points empty,
-    // .. load the index of supported grid points into `falling_grid_points` to be moved down
-    let mut falling_grid_points: Vec<usize> =
-        Vec::new();
-    let tetris_grid_time_elapsed = gameworld
-        .get_entities_by_type::<TetrisGrid>()
-        .first()
-        .unwrap()
-        .gfx_time_elapsed
-        .clone();
-    if tetris_grid_time_elapsed >= 0.25 {
-        if let Some(tetris_grid) = gameworld
-            .get_entities_by_type::<TetrisGrid>()
-            .first_mut()
-        {
-            tetris_grid
-                .matrix
-                .chunks_mut(10)
-                .rev()
-                .find(|row| {
-                    row.iter()
-                        .all(|gp| gp.point_state == TetrisGridPointState::Flashing)
-                })
-                .iter_mut()
-                .for_each(|row| {
-                    row.iter_mut().for_each(|gp| {
-                        if gp.point_state == TetrisGridPointState::Flashing {
-                            // gp.point_state = TetrisGridPointState::Empty;
-                            // gp.point_active_gfx = gfx::Name::GPpshooterBlock;
-                            gp.switch_grid_point_state(TetrisGridPointState::Empty);
+}
+/// stop the game timer at the end of the run and
+///  put its time in the best times table,
+///  returning its place in the table. until a run
+///  is saved, the table is the one shipped with
+///  the game. only called once Dr. Wily is
+///  beaten, as the table ranks whole runs rather
+///  than single stages
+async fn record_best_time(
+    gameworld: &mut GameWorld,
+) -> Result<Option<usize>, scores::ScoresError> {
+    let Some(game_timer) = gameworld
+        .get_entities_by_type::<GameTimer>()
+        .into_iter()
+        .next()
+    else {
+        return Ok(None);
+    };
+    game_timer.is_active = false;
+    let best_time = scores::BestTime::new(
+        game_timer.time_elapsed,
+        PLAYER_NAME,
+    );
+    let mut storage = scores::default_storage();
+    let mut best_times =
+        match scores::BestTimes::load(&storage)? {
+            Some(best_times) => best_times,
+            None => {
+                let text = macroquad::file::load_string(scores::SCORES_FILE)
+                .await
+                .map_err(|err| {
+                    scores::ScoresError::Storage(format!(
+                        "failed to load {}: {err}",
+                        scores::SCORES_FILE
+                    ))
+                })?;
+                scores::BestTimes::parse(&text)?
+            }
+        };
+    let place = best_times.insert(best_time);
+    if place.is_some() {
+        best_times.save(&mut storage)?;
+    }
+    Ok(place)
+}
+/// the weapon asked for with this frame's
+///  keyboard input, `Some(None)` being the P
+///  Shooter
+fn read_weapon_switch(
+    gameworld: &mut GameWorld,
+) -> Option<Option<NPCName>> {
+    // Input Switch Weapon
+    if key_pressed!(
+        gameworld.input,
+        KeyCode::F1,
+        KeyCode::Key1,
+        KeyCode::F2,
+        KeyCode::Key2,
+        KeyCode::F3,
+        KeyCode::Key3,
+        KeyCode::F4,
+        KeyCode::Key4,
+        KeyCode::F5,
+        KeyCode::Key5,
+        KeyCode::F6,
+        KeyCode::Key6,
+        KeyCode::F7,
+        KeyCode::Key7,
+        KeyCode::F8,
+        KeyCode::Key8,
+        KeyCode::F9,
+        KeyCode::Key9
+    ) {
+        let last_input = gameworld
+            .input
+            .keys_down
+            .iter()
+            .next()
+            .cloned();
+        let weapon: Option<NPCName> =
+            match last_input {
+                Some(KeyCode::F1)
+                | Some(KeyCode::Key1) => {
+                    Some(NPCName::BubbleMan)
+                }
+                Some(KeyCode::F2)
+                | Some(KeyCode::Key2) => {
+                    Some(NPCName::AirMan)
+                }
+                Some(KeyCode::F3)
+                | Some(KeyCode::Key3) => {
+                    Some(NPCName::QuickMan)
+                }
+                Some(KeyCode::F4)
+                | Some(KeyCode::Key4) => {
+                    Some(NPCName::HeatMan)
+                }
+                Some(KeyCode::F5)
+                | Some(KeyCode::Key5) => None,
+                Some(KeyCode::F6)
+                | Some(KeyCode::Key6) => {
+                    Some(NPCName::WoodMan)
+                }
+                Some(KeyCode::F7)
+                | Some(KeyCode::Key7) => {
+                    Some(NPCName::MetalMan)
+                }
+                Some(KeyCode::F8)
+                | Some(KeyCode::Key8) => {
+                    Some(NPCName::FlashMan)
+                }
+                Some(KeyCode::F9)
+                | Some(KeyCode::Key9) => {
+                    Some(NPCName::CrashMan)
+                }
+                _ => None,
+            };
+        return Some(weapon);
+    }
+    None
+}
+/// everything the player did this frame, as it
+///  gets recorded in a replay tick
+fn read_actions(
+    gameworld: &mut GameWorld,
+) -> Vec<replay::Action> {
+    let mut actions: Vec<replay::Action> =
+        read_puzzle_inputs(gameworld)
+            .into_iter()
+            .map(replay::Action::Puzzle)
+            .collect();
+    if let Some(weapon) =
+        read_weapon_switch(gameworld)
+    {
+        actions.push(
+            replay::Action::SwitchWeapon(weapon),
+        );
+    }
+    // Input Weapon Ability
+    if key_pressed!(
+        gameworld.input,
+        KeyCode::V,
+        KeyCode::Kp3
+    ) {
+        actions.push(replay::Action::UseAbility);
+    }
+    actions
+}
+/// translate this frame's keyboard, touch and
+///  gesture input into [puzzle::Input]s
+fn read_puzzle_inputs(
+    gameworld: &mut GameWorld,
+) -> Vec<puzzle::Input> {
+    let mut inputs = Vec::new();
+    // moving and dropping auto repeat in the
+    //  puzzle rules, so they look at the input
+    //  manager's keys being held rather than its
+    //  key repeats
+    let keys_down =
+        gameworld.input.keys_down.clone();
+    // Input Rotate Right (ClockWise)
+    if (gameworld.input.contains(&KeyCode::Up) || gameworld.input.contains(&KeyCode::Kp8)
+        || gameworld.input.contains(&KeyCode::Kp9) || gameworld.input.contains(&KeyCode::W)
+        || gameworld.input.contains(&KeyCode::E))
+        // TODO: update the tap gesture to detect taps within a region instead of a specific place
+        || gameworld.input.get_tap_location().is_some()
+    {
+        inputs
+            .push(puzzle::Input::RotateClockwise);
+    }
+    // Input Rotate Left
+    if gameworld.input.contains(&KeyCode::Kp7)
+        || gameworld.input.contains(&KeyCode::Q)
+    {
+        inputs.push(
+            puzzle::Input::RotateCounterClockwise,
+        );
+    }
+    // Touch Move (left or right)
+    // The bool tuple is basically indicating if
+    //  one of "move left", "move right", or
+    //  "neither" are recorded.
+    // An enum would probably be more legible and idiomatic but seems like more code for limited improved
+    // readability.
+    let mut touch_move: (bool, bool) =
+        (false, false);
+    if gameworld.input.get_gestures().contains(
+        &input_manager::Gesture::TapHold,
+    ) {
+        if let Some(active_tetris_piece) = gameworld
+            .get_entities_by_type_and_property(
+                |tetris_piece: &TetrisPiece| tetris_piece.slot,
+                TetrisPieceSlot::Active,
+            )
+            .first_mut()
+        {
+            if let Some(center_block_gfx_pos_x) = active_tetris_piece
+                .piece_type_shape
+                .iter()
+                .find(|gp| gp.point_state == TetrisGridPointState::Center)
+                .map(|gp| gp.gfx_position.x)
+            {
+                if let Some(last_touch) = touches().last() {
+                    let tap_x = last_touch.position.x;
+                    // move left
+                    if center_block_gfx_pos_x > tap_x {
+                        touch_move = (true, false);
+                    }
 
-                            falling_grid_points.push(gp.point_idx as usize);
-                        }
-                    })
-                });
+                    // move right
+                    if tap_x > center_block_gfx_pos_x {
+                        touch_move = (false, true);
+                    }
+                }
+            }
+        }
+    }
+    // Input Move Left
+    if (touch_move == (true, false)
+        || keys_down.contains(&KeyCode::Left)
+        || keys_down.contains(&KeyCode::Kp4)
+        || keys_down.contains(&KeyCode::A))
+        || gameworld
+            .input
+            .get_gestures()
+            .contains(
+            &input_manager::Gesture::SwipeLeft,
+        )
+    {
+        inputs.push(puzzle::Input::MoveLeft);
+    }
+    // Input Right - Move Piece Right
+    if (touch_move == (false, true)
+        || keys_down.contains(&KeyCode::Right)
+        || keys_down.contains(&KeyCode::Kp6)
+        || keys_down.contains(&KeyCode::D))
+        || gameworld
+            .input
+            .get_gestures()
+            .contains(
+            &input_manager::Gesture::SwipeRight,
+        )
+    {
+        inputs.push(puzzle::Input::MoveRight);
+    }
+    // Input Down - Move Piece Down
+    if keys_down.contains(&KeyCode::Down)
+        || keys_down.contains(&KeyCode::Kp2)
+        || keys_down.contains(&KeyCode::Kp5)
+        || keys_down.contains(&KeyCode::S)
+        || keys_down.contains(&KeyCode::X)
+    {
+        inputs.push(puzzle::Input::SoftDrop);
+    }
+    // Press Space to hard drop the piece straight
+    //  onto the stack
+    if key_pressed!(
+        gameworld.input,
+        KeyCode::Space,
+        KeyCode::Kp0
+    ) || gameworld
+        .input
+        .get_gestures()
+        .contains(
+            &input_manager::Gesture::SwipeDown,
+        )
+    {
+        inputs.push(puzzle::Input::HardDrop);
+    }
+    // Hold Ctrl to firm drop, the piece falls
+    //  ultra fast and locks when it lands
+    if keys_down.contains(&KeyCode::LeftControl)
+        || keys_down.contains(&KeyCode::RightControl)
+        || keys_down.contains(&KeyCode::KpDecimal)
+        || gameworld
+            .input
+            .get_gestures()
+            .contains(&input_manager::Gesture::SwipeHoldDown)
+    {
+        inputs.push(puzzle::Input::FirmDrop);
+    }
+    // Input Hold - swap the active piece with the
+    //  held piece
+    if gameworld.input.contains(&KeyCode::C)
+        || gameworld
+            .input
+            .contains(&KeyCode::LeftShift)
+        || gameworld
+            .input
+            .contains(&KeyCode::RightShift)
+        || gameworld
+            .input
+            .get_gestures()
+            .contains(
+                &input_manager::Gesture::SwipeUp,
+            )
+    {
+        inputs.push(puzzle::Input::Hold);
+    }
+    inputs
+}
+/// draw the puzzle rules by copying the engine's
+/// grid, active piece, upcoming pieces and held
+/// piece onto the [TetrisGrid] and [TetrisPiece]
+/// entities
+async fn sync_puzzle_entities(
+    gameworld: &mut GameWorld,
+) {
+    let matrix = gameworld
+        .puzzle
+        .visible_matrix()
+        .to_vec();
+    let piece = gameworld.puzzle.piece;
+    let ghost = gameworld.puzzle.ghost();
+    let piece_set =
+        &gameworld.puzzle.settings.piece_set;
+    let preview: Vec<Vec<(i32, i32)>> = gameworld
+        .puzzle
+        .preview()
+        .map(|piece_type| {
+            corner_blocks(piece_set, piece_type)
+        })
+        .collect();
+    let held_blocks = gameworld
+        .puzzle
+        .held_piece_type
+        .map(|piece_type| {
+            corner_blocks(piece_set, piece_type)
+        });
+    let can_hold = gameworld.puzzle.can_hold;
+    let grid_width = gameworld.puzzle.width();
+    let activated_weapon = gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first()
+        .unwrap()
+        .active_weapon
+        .clone();
+    if let Some(tetris_grid) = gameworld
+        .get_entities_by_type::<TetrisGrid>()
+        .first_mut()
+    {
+        for (gp, cell) in tetris_grid
+            .matrix
+            .iter_mut()
+            .zip(matrix)
+        {
+            let point_state = match cell {
+                puzzle::Cell::Empty => {
+                    TetrisGridPointState::Empty
+                }
+                puzzle::Cell::Active => {
+                    TetrisGridPointState::Active
+                }
+                puzzle::Cell::Flashing => {
+                    TetrisGridPointState::Flashing
+                }
+                puzzle::Cell::Burning(_) => {
+                    TetrisGridPointState::Burning
+                }
+                puzzle::Cell::Bomb(_) => {
+                    TetrisGridPointState::Bomb
+                }
+            };
+            if gp.point_state != point_state {
+                gp.switch_grid_point_state(
+                    point_state,
+                );
+            }
+        }
+    }
+    // ghost piece, synced before the active piece
+    //  so it's drawn behind it
+    sync_tetris_piece(
+        gameworld,
+        TetrisPieceSlot::Ghost,
+        ghost.map(|ghost| ghost.blocks()),
+        (0, 0),
+        GHOST_ALPHA,
+        activated_weapon,
+    )
+    .await;
+    // active piece
+    sync_tetris_piece(
+        gameworld,
+        TetrisPieceSlot::Active,
+        piece.map(|piece| piece.blocks()),
+        (0, 0),
+        1.,
+        activated_weapon,
+    )
+    .await;
+    // upcoming pieces, stacked on the right hand
+    //  side of the grid
+    for slot in 0..puzzle::MAX_PREVIEW_COUNT {
+        sync_tetris_piece(
+            gameworld,
+            TetrisPieceSlot::Preview(slot),
+            preview.get(slot).cloned(),
+            (
+                grid_width
+                    + PREVIEW_COLUMN_OFFSET,
+                slot as i32 * PREVIEW_SLOT_ROWS,
+            ),
+            1.,
+            activated_weapon,
+        )
+        .await;
+    }
+    // held piece, dimmed until it can be swapped
+    //  back in
+    sync_tetris_piece(
+        gameworld,
+        TetrisPieceSlot::Hold,
+        held_blocks,
+        HOLD_OFFSET,
+        if can_hold {
+            1.
+        } else {
+            HOLD_UNAVAILABLE_ALPHA
+        },
+        activated_weapon,
+    )
+    .await;
+    // weapon energy meters
+    let weapon_energy: Vec<(NPCName, u8)> =
+        ROBOT_MASTERS
+            .iter()
+            .filter_map(|weapon| {
+                weapon_ability(*weapon).map(
+                    |ability| {
+                        (
+                            *weapon,
+                            gameworld
+                                .puzzle
+                                .ability_energy(
+                                    ability,
+                                ),
+                        )
+                    },
+                )
+            })
+            .collect();
+    if let Some(weapon_box_select) = gameworld
+        .get_entities_by_type::<WeaponBoxSelect>()
+        .first_mut()
+    {
+        for (weapon, energy) in weapon_energy {
+            weapon_box_select
+                .set_energy(weapon, energy);
+        }
+    }
+}
+/// blocks of a freshly spawned piece moved into
+///  the top left corner of the grid, for drawing
+///  it outside the grid
+fn corner_blocks(
+    piece_set: &puzzle::PieceSet,
+    piece_type: puzzle::PieceType,
+) -> Vec<(i32, i32)> {
+    puzzle::Piece {
+        x: 0,
+        y: 0,
+        ..piece_set
+            .spawn(piece_type, puzzle::GRID_WIDTH)
+    }
+    .blocks()
+}
+/// keep the tetris piece entity for a slot in
+/// step with the puzzle rules: create it when
+/// there are blocks to show, remove it when there
+/// aren't, and draw it `offset` blocks (columns,
+/// rows) away from the grid
+async fn sync_tetris_piece(
+    gameworld: &mut GameWorld,
+    slot: TetrisPieceSlot,
+    blocks: Option<Vec<(i32, i32)>>,
+    offset: (i32, i32), alpha: f32,
+    activated_weapon: Option<NPCName>,
+) {
+    let grid_width = gameworld.puzzle.width();
+    let Some(blocks) = blocks else {
+        // remove the tetris piece entity so a new
+        //  one can be created
+        gameworld.entities.retain(|entity| {
+            !entity
+                .as_any()
+                .downcast_ref::<TetrisPiece>()
+                .is_some_and(|tetris_piece| {
+                    tetris_piece.slot == slot
+                })
+        });
+        return;
+    };
+    if gameworld
+        .get_entities_by_type_and_property(
+            |tetris_piece: &TetrisPiece| {
+                tetris_piece.slot
+            },
+            slot,
+        )
+        .is_empty()
+    {
+        let mut new_tetris_piece =
+            TetrisPiece::new();
+        new_tetris_piece.slot = slot;
+        new_tetris_piece
+            .set_piece_gfx(activated_weapon);
+        register_entities!(
+            gameworld,
+            new_tetris_piece
+        );
+    }
+    let Some(tetris_piece) = gameworld
+        .get_entities_by_type_and_property(
+            |tetris_piece: &TetrisPiece| {
+                tetris_piece.slot
+            },
+            slot,
+        )
+        .into_iter()
+        .next()
+    else {
+        return;
+    };
+    shape_tetris_piece(
+        tetris_piece,
+        &blocks,
+        grid_width,
+        alpha,
+    );
+    if offset == (0, 0) {
+        return;
+    }
+    let Some(origin) =
+        tetris_piece.piece_type_shape.first()
+    else {
+        return;
+    };
+    // measure a block by drawing a copy of one at
+    //  the top left grid point and its diagonal
+    //  neighbour
+    let mut probe = origin.clone();
+    probe.point_idx = 0;
+    probe.update_gfx_pos();
+    let top_left = probe.gfx_position;
+    probe.point_idx = (grid_width + 1) as _;
+    probe.update_gfx_pos();
+    let block_size =
+        probe.gfx_position - top_left;
+    for gp in
+        tetris_piece.piece_type_shape.iter_mut()
+    {
+        gp.gfx_position += vec2(
+            block_size.x * offset.0 as f32,
+            block_size.y * offset.1 as f32,
+        );
+    }
+}
+/// move a tetris piece entity's grid points onto
+/// the given blocks, the first one being the
+/// bright center block. blocks still up in the
+/// buffer rows above the grid are hidden
+fn shape_tetris_piece(
+    tetris_piece: &mut TetrisPiece,
+    blocks: &[(i32, i32)], grid_width: i32,
+    alpha: f32,
+) {
+    // pieces from a data defined set can have
+    //  more or fewer blocks than the four a
+    //  tetris piece starts with
+    if let Some(template) = tetris_piece
+        .piece_type_shape
+        .first()
+        .cloned()
+    {
+        tetris_piece
+            .piece_type_shape
+            .resize(blocks.len(), template);
+    }
+    for (block_idx, (gp, (x, y))) in tetris_piece
+        .piece_type_shape
+        .iter_mut()
+        .zip(blocks)
+        .enumerate()
+    {
+        let point_state = if block_idx == 0 {
+            TetrisGridPointState::Center
+        } else {
+            TetrisGridPointState::Active
+        };
+        if gp.point_state != point_state {
+            gp.switch_grid_point_state(
+                point_state,
+            );
+        }
+        gp.grid_width = grid_width;
+        gp.point_idx =
+            ((*y).max(0) * grid_width + x) as _;
+        gp.alpha =
+            if *y < 0 { 0. } else { alpha };
+        gp.update_gfx_pos();
+    }
+}
+/// do damage to the robot for the rows cleared on
+///  the tetris grid
+async fn damage_robot(
+    gameworld: &mut GameWorld,
+    new_rows_cleared: u8,
+    t_spin: Option<puzzle::TSpin>, chain: u32,
+) {
+    let active_weapon = gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first()
+        .unwrap()
+        .active_weapon
+        .clone();
+    let current_npc = gameworld
+        .get_entities_by_type::<NPC>()
+        .iter()
+        .find(|npc| {
+            npc.npc_state == NPCState::Gameplay
+        })
+        .unwrap()
+        .name
+        .clone();
+    let calculated_damage = calculate_damage(
+        new_rows_cleared,
+        active_weapon,
+        current_npc,
+        t_spin,
+        gameworld.puzzle.combo,
+        chain,
+        gameworld.puzzle.is_back_to_back,
+    );
+    hit_robot(gameworld, calculated_damage).await;
+}
+/// take the damage off the robot's life meter,
+///  flash it and start its explosion once it runs
+///  out of hitpoints
+async fn hit_robot(
+    gameworld: &mut GameWorld,
+    calculated_damage: u8,
+) {
+    // gameworld.get_entities_by_type::<NPC>().iter_mut().find(|npc| npc.npc_state == NPCState::Gameplay).unwrap().take_damage(16);
+    gameworld
+        .get_entities_by_type::<NPC>()
+        .iter_mut()
+        .find(|npc| {
+            npc.npc_state == NPCState::Gameplay
+        })
+        .unwrap()
+        .take_damage(calculated_damage);
+    let new_life_meter_value = gameworld
+        .get_entities_by_type_and_property(
+            |npc: &NPC| npc.npc_state,
+            NPCState::Gameplay,
+        )
+        .first_mut()
+        .unwrap()
+        .hitpoints;
+    gameworld
+        .get_entities_by_type::<LifeMeterBox>()
+        .first_mut()
+        .unwrap()
+        .qty = new_life_meter_value;
+    gameworld
+        .sfx_atlas
+        .play(sfx::SFXName::RobotHit)
+        .await;
+    if let Some(hitflash) = gameworld
+        .get_entities_by_type::<HitFlash>()
+        .first_mut()
+    {
+        if !hitflash.is_active {
+            hitflash.is_active = true;
+        }
+    }
+    if gameworld
+        .get_entities_by_type_and_property(
+            |npc: &NPC| npc.npc_state,
+            NPCState::Gameplay,
+        )
+        .first_mut()
+        .unwrap()
+        .hitpoints
+        == 0
+    {
+        gameworld
+            .get_entities_by_type::<HitFlash>()
+            .first_mut()
+            .unwrap()
+            .is_visible = false;
+        gameworld
+            .get_entities_by_type::<HitFlash>()
+            .first_mut()
+            .unwrap()
+            .is_active = false;
+        gameworld
+            .get_entities_by_type::<NPC>()
+            .iter_mut()
+            .find(|npc| {
+                npc.npc_state
+                    == NPCState::Gameplay
+            })
+            .unwrap()
+            .start_action(NPCAction::Exploding);
+    }
+}
+/// special attack for clearing the whole tetris
+/// grid: mega man fires a charged shot and the
+/// whole screen flashes as it hits for
+/// [perfect_clear_damage]
+async fn perfect_clear(
+    gameworld: &mut GameWorld,
+) {
+    let active_weapon = gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first()
+        .unwrap()
+        .active_weapon
+        .clone();
+    let current_npc = gameworld
+        .get_entities_by_type::<NPC>()
+        .iter()
+        .find(|npc| {
+            npc.npc_state == NPCState::Gameplay
+        })
+        .unwrap()
+        .name
+        .clone();
+    if let Some(megaman) = gameworld
+        .get_entities_by_type::<MegaMan>()
+        .first_mut()
+    {
+        megaman.start_action(
+            megaman::Action::PerfectClearShot,
+        );
+    }
+    if let Some(hitflash) = gameworld
+        .get_entities_by_type::<HitFlash>()
+        .first_mut()
+    {
+        hitflash.is_full_screen = true;
+        hitflash.is_visible = true;
+    }
+    hit_robot(
+        gameworld,
+        perfect_clear_damage(
+            active_weapon,
+            current_npc,
+        ),
+    )
+    .await;
+}
+/// damage for a perfect clear: a flat
+///  [PERFECT_CLEAR_DAMAGE] rather than scaling
+///  with the weapon's weakness like
+///  [calculate_damage] does, so a weak spot
+///  doesn't end the fight in one shot. a robot
+///  immune to the weapon still takes nothing
+fn perfect_clear_damage(
+    active_weapon: Option<NPCName>,
+    current_npc: NPCName,
+) -> u8 {
+    if weapon_multiplier(
+        active_weapon,
+        current_npc,
+    ) == 0
+    {
+        0
+    } else {
+        PERFECT_CLEAR_DAMAGE
+    }
+}
+/// how often, in seconds of game time, each robot
+///  attacks and how many garbage rows it sends
+fn robot_attack(
+    current_npc: NPCName,
+) -> (f32, u32) {
+    match current_npc {
+        NPCName::BubbleMan | NPCName::WoodMan => {
+            (30., 1)
+        }
+        NPCName::AirMan
+        | NPCName::HeatMan
+        | NPCName::MetalMan => (25., 1),
+        NPCName::FlashMan | NPCName::CrashMan => {
+            (25., 2)
+        }
+        NPCName::QuickMan => (15., 1),
+        NPCName::DrWily => (20., 2),
+        NPCName::RustMan => (15., 2),
+    }
+}
+/// data file with the pieces for each robot's
+///  stage, `None` for the standard tetrominoes.
+///  Dr. Wily's fortress deals the twelve
+///  pentominoes, the hardest set in the game
+fn piece_set_file(
+    current_npc: NPCName,
+) -> Option<&'static str> {
+    match current_npc {
+        NPCName::DrWily => {
+            Some("assets/pentominoes.dat")
+        }
+        NPCName::RustMan => {
+            Some("assets/rustman/crab.dat")
+        }
+        _ => None,
+    }
+}
+/// read the piece set in the data file at `path`
+async fn load_piece_set(
+    path: &str,
+) -> Result<puzzle::PieceSet, String> {
+    let text = macroquad::file::load_string(path)
+        .await
+        .map_err(|err| err.to_string())?;
+    puzzle::PieceSet::parse(&text)
+        .map_err(|err| err.to_string())
+}
+/// the special ability powered by each robot
+///  master's weapon
+fn weapon_ability(
+    weapon: NPCName,
+) -> Option<puzzle::Ability> {
+    match weapon {
+        NPCName::HeatMan => {
+            Some(puzzle::Ability::AtomicFire)
+        }
+        NPCName::AirMan => {
+            Some(puzzle::Ability::AirShooter)
+        }
+        NPCName::BubbleMan => {
+            Some(puzzle::Ability::BubbleLead)
+        }
+        NPCName::QuickMan => {
+            Some(puzzle::Ability::QuickBoomerang)
+        }
+        NPCName::FlashMan => {
+            Some(puzzle::Ability::TimeStopper)
+        }
+        NPCName::MetalMan => {
+            Some(puzzle::Ability::MetalBlade)
+        }
+        NPCName::CrashMan => {
+            Some(puzzle::Ability::CrashBomb)
+        }
+        NPCName::WoodMan => {
+            Some(puzzle::Ability::LeafShield)
+        }
+        NPCName::DrWily | NPCName::RustMan => {
+            None
+        }
+    }
+}
+/// sound of each weapon being fired, the P
+///  Shooter when no weapon is selected
+fn weapon_sfx(
+    weapon: Option<NPCName>,
+) -> sfx::SFXName {
+    match weapon {
+        Some(NPCName::BubbleMan) => {
+            sfx::SFXName::BubbleShot
         }
-        if !falling_grid_points.is_empty() {
-            if let Some(tetris_grid) = gameworld.get_entities_by_type::<TetrisGrid>().first_mut() {
-                let idx_max = falling_grid_points.iter().max().unwrap().clone();
-                for idx in (10..idx_max + 1).rev() {
-                    tetris_grid.matrix[idx].point_state = tetris_grid.matrix[idx - 10].point_state;
-                    tetris_grid.matrix[idx].point_active_gfx =
-                        tetris_grid.matrix[idx - 10].point_active_gfx;
-                }
-                tetris_grid.gfx_time_elapsed = 0.;
-            }
-        } else {
-            gameworld
-                .get_entities_by_type::<HitFlash>(
-                )
-                .first_mut()
-                .unwrap()
-                .is_visible = false;
-            gameworld
-                .get_entities_by_type::<HitFlash>(
-                )
-                .first_mut()
-                .unwrap()
-                .is_active = false;
+        Some(NPCName::AirMan) => {
+            sfx::SFXName::AirShot
+        }
+        Some(NPCName::QuickMan) => {
+            sfx::SFXName::QuickShot
+        }
+        Some(NPCName::HeatMan) => {
+            sfx::SFXName::HeatShot
+        }
+        Some(NPCName::WoodMan) => {
+            sfx::SFXName::WoodShot
         }
+        Some(NPCName::MetalMan) => {
+            sfx::SFXName::MetalShot
+        }
+        Some(NPCName::FlashMan) => {
+            sfx::SFXName::FlashShot
+        }
+        Some(NPCName::CrashMan) => {
+            sfx::SFXName::CrashShot
+        }
+        _ => sfx::SFXName::PShot,
     }
-    // do some damage
-    if new_rows_cleared > 0 {
-        let active_weapon = gameworld
-            .get_entities_by_type::<MegaMan>()
-            .first()
-            .unwrap()
-            .active_weapon
-            .clone();
-        let current_npc = gameworld
-            .get_entities_by_type::<NPC>()
-            .iter()
-            .find(|npc| {
-                npc.npc_state
-                    == NPCState::Gameplay
-            })
-            .unwrap()
-            .name
-            .clone();
-        let calculated_damage = calculate_damage(
-            new_rows_cleared,
-            active_weapon,
-            current_npc,
-        );
-        // gameworld.get_entities_by_type::<NPC>().iter_mut().find(|npc| npc.npc_state == NPCState::Gameplay).unwrap().take_damage(16);
-        gameworld
-            .get_entities_by_type::<NPC>()
-            .iter_mut()
-            .find(|npc| {
-                npc.npc_state
-                    == NPCState::Gameplay
-            })
-            .unwrap()
-            .take_damage(calculated_damage);
-        let new_life_meter_value = gameworld
-            .get_entities_by_type_and_property(
-                |npc: &NPC| npc.npc_state,
-                NPCState::Gameplay,
-            )
-            .first_mut()
-            .unwrap()
-            .hitpoints;
-        gameworld
-            .get_entities_by_type::<LifeMeterBox>(
-            )
-            .first_mut()
-            .unwrap()
-            .qty = new_life_meter_value;
-        gameworld
-            .sfx_atlas
-            .play(sfx::SFXName::RobotHit)
-            .await;
-        if let Some(hitflash) = gameworld
-            .get_entities_by_type::<HitFlash>()
-            .first_mut()
-        {
-            if !hitflash.is_active {
-                hitflash.is_active = true;
-            }
+}
+/// how often, in seconds of game time, each robot
+/// uses a board gimmick and which one it uses
+/// next. Dr. Wily and Rust Man cycle through
+/// every robot master's gimmick
+fn robot_gimmick(
+    current_npc: NPCName, hazards_triggered: u32,
+) -> (f32, puzzle::Hazard) {
+    const ALL_HAZARDS: [puzzle::Hazard; 8] = [
+        puzzle::Hazard::Gust,
+        puzzle::Hazard::Haste,
+        puzzle::Hazard::TimeStop,
+        puzzle::Hazard::Leaf,
+        puzzle::Hazard::Blade,
+        puzzle::Hazard::Ignite,
+        puzzle::Hazard::Bubble,
+        puzzle::Hazard::Bomb,
+    ];
+    match current_npc {
+        NPCName::AirMan => {
+            (8., puzzle::Hazard::Gust)
         }
-        if gameworld
-            .get_entities_by_type_and_property(
-                |npc: &NPC| npc.npc_state,
-                NPCState::Gameplay,
-            )
-            .first_mut()
-            .unwrap()
-            .hitpoints
-            == 0
-        {
-            gameworld
-                .get_entities_by_type::<HitFlash>(
-                )
-                .first_mut()
-                .unwrap()
-                .is_visible = false;
-            gameworld
-                .get_entities_by_type::<HitFlash>(
-                )
-                .first_mut()
-                .unwrap()
-                .is_active = false;
-            gameworld
-                .get_entities_by_type::<NPC>()
-                .iter_mut()
-                .find(|npc| {
-                    npc.npc_state
-                        == NPCState::Gameplay
-                })
-                .unwrap()
-                .start_action(
-                    NPCAction::Exploding,
-                );
+        NPCName::QuickMan => {
+            (20., puzzle::Hazard::Haste)
+        }
+        NPCName::FlashMan => {
+            (20., puzzle::Hazard::TimeStop)
+        }
+        NPCName::WoodMan => {
+            (6., puzzle::Hazard::Leaf)
+        }
+        NPCName::MetalMan => {
+            (10., puzzle::Hazard::Blade)
         }
+        NPCName::HeatMan => {
+            (12., puzzle::Hazard::Ignite)
+        }
+        NPCName::BubbleMan => {
+            (10., puzzle::Hazard::Bubble)
+        }
+        NPCName::CrashMan => {
+            (15., puzzle::Hazard::Bomb)
+        }
+        NPCName::DrWily | NPCName::RustMan => (
+            10.,
+            ALL_HAZARDS[hazards_triggered
+                as usize
+                % ALL_HAZARDS.len()],
+        ),
+    }
+}
+/// how quickly gravity speeds up in each robot's
+/// stage. the robots from the stage select start
+/// gentle and get faster the more damage they
+/// take, Dr. Wily and Rust Man keep the pressure
+/// on from the start
+fn gravity_curve(
+    current_npc: NPCName,
+) -> puzzle::GravityCurve {
+    let (
+        min_drop_down_speed,
+        per_row_cleared,
+        per_minute,
+        per_hitpoint_lost,
+    ) = match current_npc {
+        NPCName::BubbleMan | NPCName::WoodMan => {
+            (0.45, 0.004, 0.02, 0.01)
+        }
+        NPCName::AirMan
+        | NPCName::HeatMan
+        | NPCName::MetalMan => {
+            (0.35, 0.005, 0.03, 0.015)
+        }
+        NPCName::FlashMan | NPCName::CrashMan => {
+            (0.3, 0.006, 0.035, 0.015)
+        }
+        NPCName::QuickMan => {
+            (0.2, 0.008, 0.04, 0.02)
+        }
+        NPCName::DrWily => {
+            (0.15, 0.01, 0.05, 0.02)
+        }
+        NPCName::RustMan => {
+            (0.1, 0.012, 0.06, 0.025)
+        }
+    };
+    puzzle::GravityCurve {
+        min_drop_down_speed,
+        per_row_cleared,
+        per_minute,
+        per_hitpoint_lost,
     }
 }
-// see: https://www.mmhp.net/GameHints/MM2-Data.html#:~:text=Weapon%3A%20Bubble%20Lead,Bubble%20Man
-// see: https://docs.google.com/spreadsheets/d/1OLRnn-jMqGKO3bz4UQZqW8MhOBR-eRSZAvLogVualks/edit?gid=0#gid=0
 /// Calculates damage based on currently active weapon versus current robot stage and number of lines cleared
-/// takes rows as u8, enum, and enum
+/// takes rows as u8, enum, enum, the t-spin that
+/// cleared the rows if there was one, the combo
+/// count, the depth of the cascade chain that
+/// completed the rows, and whether the clear
+/// continued a back-to-back chain
 /// returns u8 of damage
 fn calculate_damage(
     rows: u8, active_weapon: Option<NPCName>,
     current_npc: NPCName,
+    t_spin: Option<puzzle::TSpin>, combo: u32,
+    chain: u32, is_back_to_back: bool,
+) -> u8 {
+    let multiplier = weapon_multiplier(
+        active_weapon,
+        current_npc,
+    );
+    // bonus damage for t-spins
+    let t_spin_multiplier = match t_spin {
+        None => 1,
+        Some(puzzle::TSpin::Mini) => 2,
+        Some(puzzle::TSpin::Full) => 3,
+    };
+    let damage = rows
+        .saturating_mul(multiplier)
+        .saturating_mul(t_spin_multiplier);
+    // every lock that keeps the combo going and
+    // every step of a cascade chain adds a point,
+    // weapons the robot is immune to still do
+    // nothing
+    let combo_bonus = if multiplier > 0 {
+        combo
+            .saturating_sub(1)
+            .saturating_add(chain)
+            .min(u8::MAX as u32) as u8
+    } else {
+        0
+    };
+    let damage =
+        damage.saturating_add(combo_bonus);
+    // back-to-back difficult clears do half again
+    if is_back_to_back {
+        damage.saturating_add(damage / 2)
+    } else {
+        damage
+    }
+}
+// see: https://www.mmhp.net/GameHints/MM2-Data.html#:~:text=Weapon%3A%20Bubble%20Lead,Bubble%20Man
+// see: https://docs.google.com/spreadsheets/d/1OLRnn-jMqGKO3bz4UQZqW8MhOBR-eRSZAvLogVualks/edit?gid=0#gid=0
+/// how much the active weapon multiplies damage
+///  against the current robot, 0 when the robot
+///  is immune
+fn weapon_multiplier(
+    active_weapon: Option<NPCName>,
+    current_npc: NPCName,
 ) -> u8 {
-    // DEBUG:
-    return 16;
-    let multiplier =
-        match (active_weapon, current_npc) {
-            // P Shooter
-            (None, NPCName::DrWily) => 0,
+    match (active_weapon, current_npc) {
+        // P Shooter
+        (None, NPCName::DrWily) => 0,
 
-            (
-                None,
-                NPCName::AirMan
-                | NPCName::QuickMan
-                | NPCName::HeatMan
-                | NPCName::FlashMan,
-            ) => 2,
-            (None, _) => 1,
+        (
+            None,
+            NPCName::AirMan
+            | NPCName::QuickMan
+            | NPCName::HeatMan
+            | NPCName::FlashMan,
+        ) => 2,
+        (None, _) => 1,
 
-            // Bubble Weapon
-            (
-                Some(NPCName::BubbleMan),
-                NPCName::WoodMan
-                | NPCName::AirMan
-                | NPCName::QuickMan
-                | NPCName::MetalMan,
-            ) => 0,
-            (
-                Some(NPCName::BubbleMan),
-                NPCName::FlashMan
-                | NPCName::DrWily,
-            ) => 2,
-            (
-                Some(NPCName::BubbleMan),
-                NPCName::HeatMan,
-            ) => 4,
+        // Bubble Weapon
+        (
+            Some(NPCName::BubbleMan),
+            NPCName::WoodMan
+            | NPCName::AirMan
+            | NPCName::QuickMan
+            | NPCName::MetalMan,
+        ) => 0,
+        (
+            Some(NPCName::BubbleMan),
+            NPCName::FlashMan | NPCName::DrWily,
+        ) => 2,
+        (
+            Some(NPCName::BubbleMan),
+            NPCName::HeatMan,
+        ) => 4,
 
-            (
-                Some(NPCName::AirMan),
-                NPCName::BubbleMan
-                | NPCName::MetalMan
-                | NPCName::FlashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::AirMan),
-                NPCName::QuickMan
-                | NPCName::HeatMan,
-            ) => 2,
-            (
-                Some(NPCName::AirMan),
-                NPCName::WoodMan,
-            ) => 3,
-            (
-                Some(NPCName::AirMan),
-                NPCName::CrashMan,
-            ) => 4,
+        (
+            Some(NPCName::AirMan),
+            NPCName::BubbleMan
+            | NPCName::MetalMan
+            | NPCName::FlashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::AirMan),
+            NPCName::QuickMan | NPCName::HeatMan,
+        ) => 2,
+        (
+            Some(NPCName::AirMan),
+            NPCName::WoodMan,
+        ) => 3,
+        (
+            Some(NPCName::AirMan),
+            NPCName::CrashMan,
+        ) => 4,
 
-            (
-                Some(NPCName::QuickMan),
-                NPCName::WoodMan
-                | NPCName::FlashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::QuickMan),
-                NPCName::AirMan
-                | NPCName::HeatMan,
-            ) => 2,
-            (
-                Some(NPCName::QuickMan),
-                NPCName::MetalMan,
-            ) => 3,
+        (
+            Some(NPCName::QuickMan),
+            NPCName::WoodMan
+            | NPCName::FlashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::QuickMan),
+            NPCName::AirMan | NPCName::HeatMan,
+        ) => 2,
+        (
+            Some(NPCName::QuickMan),
+            NPCName::MetalMan,
+        ) => 3,
 
-            (
-                Some(NPCName::HeatMan),
-                NPCName::BubbleMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::HeatMan),
-                NPCName::AirMan
-                | NPCName::QuickMan
-                | NPCName::FlashMan,
-            ) => 2,
-            (
-                Some(NPCName::HeatMan),
-                NPCName::WoodMan,
-            ) => 3,
+        (
+            Some(NPCName::HeatMan),
+            NPCName::BubbleMan | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::HeatMan),
+            NPCName::AirMan
+            | NPCName::QuickMan
+            | NPCName::FlashMan,
+        ) => 2,
+        (
+            Some(NPCName::HeatMan),
+            NPCName::WoodMan,
+        ) => 3,
 
-            (
-                Some(NPCName::WoodMan),
-                NPCName::BubbleMan
-                | NPCName::QuickMan
-                | NPCName::HeatMan
-                | NPCName::MetalMan
-                | NPCName::FlashMan
-                | NPCName::CrashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::WoodMan),
-                NPCName::AirMan,
-            ) => 4,
+        (
+            Some(NPCName::WoodMan),
+            NPCName::BubbleMan
+            | NPCName::QuickMan
+            | NPCName::HeatMan
+            | NPCName::MetalMan
+            | NPCName::FlashMan
+            | NPCName::CrashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::WoodMan),
+            NPCName::AirMan,
+        ) => 4,
 
-            (
-                Some(NPCName::MetalMan),
-                NPCName::AirMan
-                | NPCName::QuickMan
-                | NPCName::CrashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::MetalMan),
-                NPCName::WoodMan,
-            ) => 2,
-            (
-                Some(NPCName::MetalMan),
-                NPCName::BubbleMan
-                | NPCName::FlashMan,
-            ) => 3,
+        (
+            Some(NPCName::MetalMan),
+            NPCName::AirMan
+            | NPCName::QuickMan
+            | NPCName::CrashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::MetalMan),
+            NPCName::WoodMan,
+        ) => 2,
+        (
+            Some(NPCName::MetalMan),
+            NPCName::BubbleMan
+            | NPCName::FlashMan,
+        ) => 3,
 
-            (
-                Some(NPCName::FlashMan),
-                NPCName::BubbleMan
-                | NPCName::AirMan
-                | NPCName::HeatMan
-                | NPCName::WoodMan
-                | NPCName::MetalMan
-                | NPCName::FlashMan
-                | NPCName::CrashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::FlashMan),
-                NPCName::QuickMan,
-            ) => 4,
+        (
+            Some(NPCName::FlashMan),
+            NPCName::BubbleMan
+            | NPCName::AirMan
+            | NPCName::HeatMan
+            | NPCName::WoodMan
+            | NPCName::MetalMan
+            | NPCName::FlashMan
+            | NPCName::CrashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::FlashMan),
+            NPCName::QuickMan,
+        ) => 4,
 
-            (
-                Some(NPCName::CrashMan),
-                NPCName::AirMan
-                | NPCName::HeatMan
-                | NPCName::MetalMan
-                | NPCName::CrashMan
-                | NPCName::DrWily,
-            ) => 0,
-            (
-                Some(NPCName::CrashMan),
-                NPCName::BubbleMan
-                | NPCName::QuickMan
-                | NPCName::WoodMan,
-            ) => 2,
-            (
-                Some(NPCName::CrashMan),
-                NPCName::FlashMan,
-            ) => 3,
+        (
+            Some(NPCName::CrashMan),
+            NPCName::AirMan
+            | NPCName::HeatMan
+            | NPCName::MetalMan
+            | NPCName::CrashMan
+            | NPCName::DrWily,
+        ) => 0,
+        (
+            Some(NPCName::CrashMan),
+            NPCName::BubbleMan
+            | NPCName::QuickMan
+            | NPCName::WoodMan,
+        ) => 2,
+        (
+            Some(NPCName::CrashMan),
+            NPCName::FlashMan,
+        ) => 3,
 
-            // TODO: custom calcs for rustman
-            (_, _) => 1,
-        };
-    rows * multiplier
-}
-/// check if a new tetris piece can be placed in the tetris grid in order to check losing condition
-fn is_piece_overlapping_grid_points(
-    gameworld: &mut GameWorld,
-    piece: &TetrisPiece,
-) -> bool {
-    let tetris_grid_points = gameworld
-        .get_entities_by_type::<TetrisGrid>()
-        .first()
-        .unwrap()
-        .matrix
-        .clone();
-    // check if any of the tetris grid points are active that the tetris piece would occupy
-    !piece.piece_type_shape.iter().any(|gp| {
-        tetris_grid_points[gp.point_idx as usize]
-            .point_state
-            == TetrisGridPointState::Active
-    })
-}
-/// check if any of the active tetris piece's grid points are out of bounds vertically (negative y value)
-// TODO: check if a placed piece has any point above the playfield
-fn is_piece_above_grid_points(
-    piece: &TetrisPiece,
-) -> bool {
-    // get highest (lowest y value) of active tetris piece. If its negative, then return true
-    let lowest_grid_point = piece
-        .piece_type_shape
-        .iter()
-        .map(|gp| {
-            entities::idx_to_grid_point(
-                gp.point_idx,
-            )
-            .y
-        })
-        .min()
-        .unwrap()
-        .clone();
-    lowest_grid_point < 0
-}
-/// Check if the active tetris piece has collided with another piece or the bottom row
-fn process_collisions(
-    gameworld: &mut GameWorld,
-) -> bool {
-    let mut is_collided = false;
-    let mut active_tetris_piece_grid_points =
-        gameworld
-            .entities
-            .iter_mut()
-            .find(|e| {
-                e.as_any().is::<TetrisPiece>()
-            })
-            .unwrap()
-            .as_any()
-            .downcast_ref::<TetrisPiece>()
-            .unwrap()
-            .piece_type_shape
-            .clone();
-    let lowest_grid_point =
-        active_tetris_piece_grid_points
-            .iter()
-            .map(|gp| {
-                entities::idx_to_grid_point(
-                    gp.point_idx,
-                )
-                .y
-            })
-            .max()
-            .unwrap()
-            .clone();
-    let tetris_grid = gameworld
-        .entities
-        .iter_mut()
-        .find(|e| e.as_any().is::<TetrisGrid>())
-        .unwrap()
-        .as_any_mut()
-        .downcast_mut::<TetrisGrid>()
-        .unwrap();
-    // check if the active piece is about to collide
-    for gp in
-        active_tetris_piece_grid_points.iter_mut()
-    {
-        // this 'or' must short circuit else index out of bounds
-        if lowest_grid_point >= 13
-            || tetris_grid.matrix
-                [gp.point_idx as usize + 10]
-                .point_state
-                == TetrisGridPointState::Active
-        {
-            is_collided = true;
-            break;
-        }
+        // TODO: custom calcs for rustman
+        (_, _) => 1,
     }
-    // if collision has occured then update the tetris grid
-    if is_collided {
-        for gp in active_tetris_piece_grid_points
-            .iter_mut()
-        {
-            // tetris_grid.matrix[gp.point_idx as usize].point_state = TetrisGridPointState::Active;
-            tetris_grid.matrix
-                [gp.point_idx as usize]
-                .switch_grid_point_state(
-                    TetrisGridPointState::Active,
-                );
-        }
-        // remove active tetris piece entity so new one can be created
-        gameworld.entities.retain(|entity| {
-            !entity.as_any().is::<TetrisPiece>()
-        });
+}
+/// sfx for a combo, borrowing sounds the stage
+///  already loads and getting bigger as the combo
+///  gets longer
+fn combo_sfx(combo: u32) -> sfx::SFXName {
+    match combo {
+        ..=3 => sfx::SFXName::LifeMeterFill,
+        4 => sfx::SFXName::WarpIn,
+        _ => sfx::SFXName::WarpOut,
     }
-    is_collided
 }
 async fn manage_music(gameworld: &mut GameWorld) {
+    let simulation = gameworld.simulation.clone();
     // Use fold to find both intro and looped tracks in one pass
     if let (
         Some(intro_music),
@@ -3178,7 +4542,8 @@ async fn manage_music(gameworld: &mut GameWorld) {
         // for each type of intro, check if it is ended and start playing looped music
         match (
             &intro_music.name,
-            intro_music.elapsed,
+            simulation
+                .music_elapsed(&intro_music.name),
         ) {
             (
                 TrackName::BubbleManIntro,
@@ -3197,3 +4562,163 @@ async fn manage_music(gameworld: &mut GameWorld) {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn t_spins_multiply_damage() {
+        let damage = |t_spin| {
+            calculate_damage(
+                2,
+                None,
+                NPCName::BubbleMan,
+                t_spin,
+                1,
+                0,
+                false,
+            )
+        };
+        assert_eq!(damage(None), 2);
+        assert_eq!(
+            damage(Some(puzzle::TSpin::Mini)),
+            4
+        );
+        assert_eq!(
+            damage(Some(puzzle::TSpin::Full)),
+            6
+        );
+        // immune robots still take nothing
+        assert_eq!(
+            calculate_damage(
+                2,
+                Some(NPCName::WoodMan),
+                NPCName::BubbleMan,
+                Some(puzzle::TSpin::Full),
+                1,
+                0,
+                false,
+            ),
+            0
+        );
+    }
+    #[test]
+    fn combos_and_back_to_back_add_damage() {
+        let damage = |combo, is_back_to_back| {
+            calculate_damage(
+                1,
+                None,
+                NPCName::BubbleMan,
+                None,
+                combo,
+                0,
+                is_back_to_back,
+            )
+        };
+        assert_eq!(damage(1, false), 1);
+        assert_eq!(damage(3, false), 3);
+        assert_eq!(damage(3, true), 4);
+        assert!(
+            damage(5, false) > damage(4, false)
+        );
+    }
+    #[test]
+    fn password_entry_goes_back_to_stage_select()
+    {
+        let mut entry =
+            password::Entry::default();
+        let is_entering = |outcome| {
+            let state =
+                password_entry_state(&outcome);
+            matches!(
+                state.state,
+                State::PasswordEntry
+            ) && state.is_initialized
+        };
+        let is_back_at_stage_select = |outcome| {
+            let state =
+                password_entry_state(&outcome);
+            matches!(
+                state.state,
+                State::StageSelect
+            ) && !state.is_initialized
+        };
+        assert!(is_entering(
+            entry.press(
+                password::EntryKey::Toggle
+            )
+        ));
+        assert!(is_entering(
+            entry.press(
+                password::EntryKey::Submit
+            )
+        ));
+        assert!(is_back_at_stage_select(
+            entry.press(password::EntryKey::Back)
+        ));
+        let run = password::Progress {
+            defeated_robots: vec![
+                NPCName::AirMan,
+            ],
+            timer_bucket: 1,
+        };
+        entry.password =
+            password::Password::encode(&run);
+        assert!(is_back_at_stage_select(
+            entry.press(
+                password::EntryKey::Submit
+            )
+        ));
+    }
+    #[test]
+    fn perfect_clears_hit_flat_unless_immune() {
+        // Air Man's weak spot doubles line clears
+        //  but not the perfect clear
+        assert_eq!(
+            perfect_clear_damage(
+                None,
+                NPCName::AirMan
+            ),
+            PERFECT_CLEAR_DAMAGE
+        );
+        assert_eq!(
+            perfect_clear_damage(
+                Some(NPCName::WoodMan),
+                NPCName::AirMan
+            ),
+            PERFECT_CLEAR_DAMAGE
+        );
+        // the P Shooter does nothing to Dr. Wily,
+        //  and neither does its perfect clear
+        assert_eq!(
+            perfect_clear_damage(
+                None,
+                NPCName::DrWily
+            ),
+            0
+        );
+        assert_eq!(
+            perfect_clear_damage(
+                Some(NPCName::FlashMan),
+                NPCName::MetalMan
+            ),
+            0
+        );
+    }
+    #[test]
+    fn chains_hit_harder_than_single_clears() {
+        let damage = |chain| {
+            calculate_damage(
+                1,
+                None,
+                NPCName::BubbleMan,
+                None,
+                1,
+                chain,
+                false,
+            )
+        };
+        assert_eq!(damage(0), 1);
+        assert_eq!(damage(1), 2);
+        assert!(damage(2) > damage(1));
+    }
+}
@@ -0,0 +1,169 @@
+//! the player's options in `options.dat`, a line for each with its name and value like
+//! `preview-count 3` or `ghost off`. options left out keep their defaults. the file is saved with
+//! the defaults the first time a stage starts, so on the desktop there's a file in the data
+//! directory to edit
+use super::puzzle;
+use super::storage::{self, Storage};
+/// where the options are kept
+pub const OPTIONS_FILE: &str = "options.dat";
+/// the names options are written under
+const OPTION_NAMES: [&str; 2] = ["preview-count", "ghost"];
+/// the puzzle settings the player can change
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// see [puzzle::Settings::preview_count]
+    pub preview_count: usize,
+    /// see [puzzle::Settings::show_ghost]
+    pub show_ghost: bool,
+}
+/// an options file that couldn't be read, with the line it went wrong on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionsError {
+    pub line: usize,
+    pub reason: String,
+}
+impl std::fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+impl Default for Options {
+    fn default() -> Self {
+        let settings = puzzle::Settings::default();
+        Self {
+            preview_count: settings.preview_count,
+            show_ghost: settings.show_ghost,
+        }
+    }
+}
+impl std::fmt::Display for Options {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "preview-count {}", self.preview_count)?;
+        writeln!(f, "ghost {}", if self.show_ghost { "on" } else { "off" })
+    }
+}
+impl Options {
+    /// read an options file written by [Options]'s `Display` or by hand
+    pub fn parse(text: &str) -> Result<Self, OptionsError> {
+        let mut options = Options::default();
+        for (line_idx, line) in text.lines().enumerate() {
+            let error = |reason: String| OptionsError {
+                line: line_idx + 1,
+                reason,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => {}
+                ["preview-count", count] => {
+                    options.preview_count = count
+                        .parse()
+                        .ok()
+                        .filter(|count| {
+                            (puzzle::MIN_PREVIEW_COUNT..=puzzle::MAX_PREVIEW_COUNT).contains(count)
+                        })
+                        .ok_or_else(|| {
+                            error(format!(
+                                "preview-count should be {} to {}, not `{count}`",
+                                puzzle::MIN_PREVIEW_COUNT,
+                                puzzle::MAX_PREVIEW_COUNT
+                            ))
+                        })?;
+                }
+                ["ghost", "on"] => options.show_ghost = true,
+                ["ghost", "off"] => options.show_ghost = false,
+                ["ghost", value] => {
+                    return Err(error(format!("ghost should be on or off, not `{value}`")));
+                }
+                [name, ..] if OPTION_NAMES.contains(&name) => {
+                    return Err(error(format!("{name} takes one value")));
+                }
+                [name, ..] => return Err(error(format!("unknown option `{name}`"))),
+            }
+        }
+        Ok(options)
+    }
+    /// the settings a new stage is played with
+    pub fn settings(&self) -> puzzle::Settings {
+        puzzle::Settings {
+            preview_count: self.preview_count,
+            show_ghost: self.show_ghost,
+            ..puzzle::Settings::default()
+        }
+    }
+    /// the options kept in `storage`, `None` when they haven't been saved yet
+    pub fn load(storage: &impl Storage) -> Result<Option<Self>, String> {
+        storage
+            .read()?
+            .map(|text| Self::parse(&text).map_err(|err| err.to_string()))
+            .transpose()
+    }
+    pub fn save(&self, storage: &mut impl Storage) -> Result<(), String> {
+        storage.write(&self.to_string())
+    }
+}
+/// where the build being run keeps the options
+pub fn default_storage() -> impl Storage {
+    storage::storage(OPTIONS_FILE)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn options_left_out_keep_their_defaults() {
+        assert_eq!(Options::parse(""), Ok(Options::default()));
+        assert_eq!(Options::parse("\n\n"), Ok(Options::default()));
+        let options = Options::parse("preview-count 5\n").unwrap();
+        assert_eq!(options.preview_count, 5);
+        assert_eq!(options.settings().preview_count, 5);
+        assert!(options.settings().show_ghost);
+        let options = Options::parse(
+            "ghost off
+preview-count 1",
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                preview_count: 1,
+                show_ghost: false
+            }
+        );
+        assert!(!options.settings().show_ghost);
+        // the rest of the rules stay as they are
+        assert_eq!(
+            options.settings().das_ms,
+            puzzle::Settings::default().das_ms
+        );
+        assert_eq!(Options::parse(&options.to_string()), Ok(options));
+    }
+    #[test]
+    fn hand_edited_mistakes_point_at_the_line() {
+        let refused = |text: &str| Options::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            refused("preview-count 0"),
+            "line 1: preview-count should be 1 to 5, not `0`"
+        );
+        assert_eq!(
+            refused("\npreview-count 6"),
+            "line 2: preview-count should be 1 to 5, not `6`"
+        );
+        assert_eq!(
+            refused("preview-count three"),
+            "line 1: preview-count should be 1 to 5, not `three`"
+        );
+        assert_eq!(
+            refused("preview-count"),
+            "line 1: preview-count takes one value"
+        );
+        assert_eq!(
+            refused("preview-count 3 4"),
+            "line 1: preview-count takes one value"
+        );
+        assert_eq!(
+            refused("preview-count 2\nghost yes"),
+            "line 2: ghost should be on or off, not `yes`"
+        );
+        assert_eq!(refused("ghost"), "line 1: ghost takes one value");
+        assert_eq!(refused("previews 3"), "line 1: unknown option `previews`");
+    }
+}
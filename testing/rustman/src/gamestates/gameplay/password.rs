@@ -0,0 +1,308 @@
+//! Mega Man 2 style passwords for carrying a run's progress between sessions: nine dots on a 5x5
+//! grid with rows A to E and columns 1 to 5. the one dot in row A is the run timer's bucket,
+//! standing in for the original's energy tanks, and like them it shifts where the robots' dots land
+//! in rows B to E
+use super::ROBOT_MASTERS;
+use crate::npc::NPCName;
+/// rows and columns in the password grid
+pub const PASSWORD_SIZE: usize = 5;
+/// cells in rows B to E, the ones the robots' dots go in
+const ROBOT_CELLS: usize = PASSWORD_SIZE * (PASSWORD_SIZE - 1);
+/// seconds of game time in each timer bucket, the last bucket taking any time past it
+pub const TIMER_BUCKET_SECONDS: f32 = 600.;
+/// cells in rows B to E, counting from B1, for each robot master still standing and defeated,
+/// in the order of [ROBOT_MASTERS]. the four cells left over never get a dot
+const ROBOT_DOTS: [(usize, usize); 8] = [
+    (0, 13),
+    (5, 17),
+    (9, 2),
+    (14, 6),
+    (18, 10),
+    (7, 19),
+    (12, 4),
+    (16, 1),
+];
+/// which cells have a dot, row A first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Password {
+    pub dots: [[bool; PASSWORD_SIZE]; PASSWORD_SIZE],
+}
+/// what a password keeps of a run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// in the order of [ROBOT_MASTERS]
+    pub defeated_robots: Vec<NPCName>,
+    /// see [timer_bucket]
+    pub timer_bucket: usize,
+}
+/// a password that doesn't belong to any run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordError {
+    /// row A needs exactly one dot
+    TimerDots(usize),
+    /// rows B to E need exactly one dot for each robot master
+    RobotDots(usize),
+    /// the dots in rows B to E don't line up with the robot masters
+    Mismatch,
+}
+impl std::fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PasswordError::TimerDots(dots) => write!(f, "row A needs 1 dot, not {dots}"),
+            PasswordError::RobotDots(dots) => {
+                write!(
+                    f,
+                    "rows B to E need {} dots, not {dots}",
+                    ROBOT_MASTERS.len()
+                )
+            }
+            PasswordError::Mismatch => {
+                write!(f, "the dots in rows B to E don't match any run")
+            }
+        }
+    }
+}
+/// a cell written the way the original's passwords are, like `C4`
+pub fn cell_name(row: usize, col: usize) -> String {
+    format!("{}{}", (b'A' + row as u8) as char, col + 1)
+}
+/// which bucket a run's game time falls in, 0 to 4
+pub fn timer_bucket(seconds: f32) -> usize {
+    ((seconds / TIMER_BUCKET_SECONDS) as usize).min(PASSWORD_SIZE - 1)
+}
+impl std::fmt::Display for Password {
+    /// the dotted cells in reading order, like `A2 B3 C1 ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let cells: Vec<String> = (0..PASSWORD_SIZE)
+            .flat_map(|row| (0..PASSWORD_SIZE).map(move |col| (row, col)))
+            .filter(|(row, col)| self.dots[*row][*col])
+            .map(|(row, col)| cell_name(row, col))
+            .collect();
+        write!(f, "{}", cells.join(" "))
+    }
+}
+/// row and column of a cell from [ROBOT_DOTS], shifted along by the timer bucket
+fn robot_cell(cell: usize, timer_bucket: usize) -> (usize, usize) {
+    let cell = (cell + timer_bucket) % ROBOT_CELLS;
+    (1 + cell / PASSWORD_SIZE, cell % PASSWORD_SIZE)
+}
+impl Password {
+    pub fn encode(progress: &Progress) -> Self {
+        let mut password = Password::default();
+        // a bucket past the last column is read back as the last one
+        let timer_bucket = progress.timer_bucket.min(PASSWORD_SIZE - 1);
+        password.dots[0][timer_bucket] = true;
+        for (robot, (standing_cell, defeated_cell)) in ROBOT_MASTERS.iter().zip(ROBOT_DOTS) {
+            let cell = if progress.defeated_robots.contains(robot) {
+                defeated_cell
+            } else {
+                standing_cell
+            };
+            let (row, col) = robot_cell(cell, timer_bucket);
+            password.dots[row][col] = true;
+        }
+        password
+    }
+    pub fn decode(&self) -> Result<Progress, PasswordError> {
+        let timer_dots: Vec<usize> = (0..PASSWORD_SIZE)
+            .filter(|col| self.dots[0][*col])
+            .collect();
+        let [timer_bucket] = timer_dots[..] else {
+            return Err(PasswordError::TimerDots(timer_dots.len()));
+        };
+        let robot_dots = self.dots[1..]
+            .iter()
+            .flatten()
+            .filter(|is_dot| **is_dot)
+            .count();
+        if robot_dots != ROBOT_MASTERS.len() {
+            return Err(PasswordError::RobotDots(robot_dots));
+        }
+        let mut defeated_robots = Vec::new();
+        for (robot, (standing_cell, defeated_cell)) in ROBOT_MASTERS.iter().zip(ROBOT_DOTS) {
+            let is_dot = |cell| {
+                let (row, col) = robot_cell(cell, timer_bucket);
+                self.dots[row][col]
+            };
+            match (is_dot(standing_cell), is_dot(defeated_cell)) {
+                (true, false) => {}
+                (false, true) => defeated_robots.push(*robot),
+                _ => return Err(PasswordError::Mismatch),
+            }
+        }
+        Ok(Progress {
+            defeated_robots,
+            timer_bucket,
+        })
+    }
+}
+/// a key on the password entry screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// add or take away the dot under the cursor
+    Toggle,
+    Submit,
+    /// back out to stage select
+    Back,
+}
+/// where the password entry screen goes after a key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryOutcome {
+    /// still entering dots
+    Editing,
+    /// the dots don't belong to any run. like the original, the screen shows an error until the
+    /// dots are changed
+    Refused(PasswordError),
+    /// carry on the run the password was given for
+    Accepted(Progress),
+    /// back out without changing anything
+    Cancelled,
+}
+/// the dots entered so far and the cell under the cursor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub password: Password,
+    /// row and column, starting in A1
+    pub cursor: (usize, usize),
+}
+impl Entry {
+    /// move the cursor, which wraps around the edges of the grid, toggle a dot or try the
+    /// password
+    pub fn press(&mut self, key: EntryKey) -> EntryOutcome {
+        let (row, col) = self.cursor;
+        let step = |cell: usize, by: usize| (cell + by) % PASSWORD_SIZE;
+        match key {
+            EntryKey::Up => self.cursor = (step(row, PASSWORD_SIZE - 1), col),
+            EntryKey::Down => self.cursor = (step(row, 1), col),
+            EntryKey::Left => self.cursor = (row, step(col, PASSWORD_SIZE - 1)),
+            EntryKey::Right => self.cursor = (row, step(col, 1)),
+            EntryKey::Toggle => self.password.dots[row][col] = !self.password.dots[row][col],
+            EntryKey::Submit => {
+                return match self.password.decode() {
+                    Ok(progress) => EntryOutcome::Accepted(progress),
+                    Err(err) => EntryOutcome::Refused(err),
+                };
+            }
+            EntryKey::Back => return EntryOutcome::Cancelled,
+        }
+        EntryOutcome::Editing
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// the progress with the robot masters picked by the bits of `mask` defeated
+    fn progress(mask: u32, timer_bucket: usize) -> Progress {
+        let defeated_robots = ROBOT_MASTERS
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| mask >> idx & 1 == 1)
+            .map(|(_, robot)| *robot)
+            .collect();
+        Progress {
+            defeated_robots,
+            timer_bucket,
+        }
+    }
+    #[test]
+    fn every_run_reads_back_from_its_password() {
+        for mask in 0..1 << ROBOT_MASTERS.len() {
+            for timer_bucket in 0..PASSWORD_SIZE {
+                let progress = progress(mask, timer_bucket);
+                let password = Password::encode(&progress);
+                assert_eq!(
+                    password.dots.iter().flatten().filter(|dot| **dot).count(),
+                    9
+                );
+                assert_eq!(password.decode(), Ok(progress));
+            }
+        }
+    }
+    #[test]
+    fn miscounted_dots_are_refused() {
+        assert_eq!(
+            Password::default().decode(),
+            Err(PasswordError::TimerDots(0))
+        );
+        let password = Password::encode(&progress(0b0000_0100, 2));
+        let mut two_timers = password;
+        two_timers.dots[0][0] = true;
+        assert_eq!(two_timers.decode(), Err(PasswordError::TimerDots(2)));
+        let mut extra_robot = password;
+        extra_robot.dots[4][4] = !extra_robot.dots[4][4];
+        assert!(matches!(
+            extra_robot.decode(),
+            Err(PasswordError::RobotDots(_))
+        ));
+    }
+    #[test]
+    fn moved_dots_never_read_as_the_same_run() {
+        let original = progress(0b1010_0110, 1);
+        let password = Password::encode(&original);
+        let robot_cells =
+            || (1..PASSWORD_SIZE).flat_map(|row| (0..PASSWORD_SIZE).map(move |col| (row, col)));
+        for (from_row, from_col) in robot_cells().filter(|(row, col)| password.dots[*row][*col]) {
+            for (to_row, to_col) in robot_cells().filter(|(row, col)| !password.dots[*row][*col]) {
+                let mut moved = password;
+                moved.dots[from_row][from_col] = false;
+                moved.dots[to_row][to_col] = true;
+                match moved.decode() {
+                    Ok(progress) => assert_ne!(progress, original),
+                    Err(err) => assert_eq!(err, PasswordError::Mismatch),
+                }
+            }
+        }
+    }
+    #[test]
+    fn game_time_is_bucketed() {
+        assert_eq!(timer_bucket(0.), 0);
+        assert_eq!(timer_bucket(TIMER_BUCKET_SECONDS - 0.01), 0);
+        assert_eq!(timer_bucket(TIMER_BUCKET_SECONDS), 1);
+        assert_eq!(timer_bucket(1250.), 2);
+        assert_eq!(timer_bucket(99_999.), PASSWORD_SIZE - 1);
+        // a timer that never started reads as the first bucket
+        assert_eq!(timer_bucket(-5.), 0);
+        assert_eq!(timer_bucket(f32::NAN), 0);
+        assert_eq!(cell_name(2, 3), "C4");
+    }
+    #[test]
+    fn long_runs_share_the_last_bucket() {
+        let password = Password::encode(&progress(0b0011_0000, 9));
+        assert_eq!(password, Password::encode(&progress(0b0011_0000, 4)));
+        assert_eq!(password.decode(), Ok(progress(0b0011_0000, 4)));
+    }
+    #[test]
+    fn passwords_are_entered_a_dot_at_a_time() {
+        let run = progress(0b1000_0001, 3);
+        let wanted = Password::encode(&run);
+        let mut entry = Entry::default();
+        // walk the cursor along each row, going up from A to wrap round to E and back down
+        assert_eq!(entry.press(EntryKey::Up), EntryOutcome::Editing);
+        assert_eq!(entry.cursor, (PASSWORD_SIZE - 1, 0));
+        entry.press(EntryKey::Down);
+        for row in 0..PASSWORD_SIZE {
+            for col in 0..PASSWORD_SIZE {
+                assert_eq!(entry.cursor, (row, col));
+                if wanted.dots[row][col] {
+                    entry.press(EntryKey::Toggle);
+                }
+                entry.press(EntryKey::Right);
+            }
+            entry.press(EntryKey::Down);
+        }
+        // back in A1 with a second timer dot, which is refused until it's taken away again
+        assert_eq!(entry.cursor, (0, 0));
+        entry.press(EntryKey::Toggle);
+        assert_eq!(
+            entry.press(EntryKey::Submit),
+            EntryOutcome::Refused(PasswordError::TimerDots(2))
+        );
+        entry.press(EntryKey::Toggle);
+        assert_eq!(entry.press(EntryKey::Submit), EntryOutcome::Accepted(run));
+        assert_eq!(entry.press(EntryKey::Back), EntryOutcome::Cancelled);
+    }
+}
@@ -0,0 +1,2502 @@
+//! the falling block rules, kept free of macroquad and the [GameWorld] so they can run headless.
+//! `gameplay()` feeds the [puzzle::Engine] abstract inputs and the frame time, reacts to the events
+//! it returns and then draws the [TetrisGrid] and [TetrisPiece] entities from the engine's state
+use std::collections::VecDeque;
+/// time in seconds that must elapse for a tetris piece to move down, before a [GravityCurve] speeds
+/// it up. also the time to wait after a lock or clear before the next piece comes in
+pub const DROP_DOWN_SPEED: f32 = 0.75;
+/// time in seconds that must elapse before repeating a drop down
+pub const DROP_DOWN_CYCLE_TIME: f32 = 0.0125;
+/// time in seconds that a completed row flashes before it is removed
+pub const ROW_FLASH_TIME: f32 = 0.25;
+/// time in seconds a piece can rest on the stack before it locks
+pub const LOCK_DELAY: f32 = 0.5;
+/// how many times moving or rotating a resting piece can restart the lock delay before it locks
+/// anyway
+pub const MAX_LOCK_RESETS: u32 = 15;
+/// number of columns in the standard tetris grid
+pub const GRID_WIDTH: i32 = 10;
+/// number of rows in the standard tetris grid
+pub const GRID_HEIGHT: i32 = 14;
+/// hidden rows above the tetris grid that pieces spawn into, they have negative y coordinates
+pub const BUFFER_ROWS: i32 = 2;
+/// size of the visible tetris grid in blocks, not counting the [BUFFER_ROWS]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dimensions {
+    pub width: i32,
+    pub height: i32,
+}
+impl Default for Dimensions {
+    fn default() -> Self {
+        Self {
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+        }
+    }
+}
+/// state of a single point on the grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cell {
+    Empty,
+    Active,
+    Flashing,
+    /// set alight by [Hazard::Ignite], burns away after this many more locks
+    Burning(u8),
+    /// planted by [Hazard::Bomb], blows up the blocks around it after this many more locks
+    Bomb(u8),
+}
+impl Cell {
+    /// a block that pieces rest on and that counts towards completing a row
+    pub fn is_solid(self) -> bool {
+        matches!(self, Cell::Active | Cell::Burning(_) | Cell::Bomb(_))
+    }
+}
+/// most blocks a piece in a [PieceSet] can be made of
+pub const MAX_PIECE_CELLS: usize = 8;
+/// the standard seven tetrominoes with their SRS rotation states, the [PieceSet] used unless a
+/// stage loads its own. see [PieceSet::parse] for the format
+pub const TETROMINOES: &str = "\
+piece I
+kicks srs-i
+.... ..#. .... .#..
+#@## ..@. .... .#..
+.... ..#. ##@# .@..
+.... ..#. .... .#..
+
+piece O
+kicks none
+@# #@ ## ##
+## ## #@ @#
+
+piece T
+t-spin
+.#. .#. ... .#.
+#@# .@# #@# #@.
+... .#. .#. .#.
+
+piece S
+.## .#. ... #..
+#@. .@# .@# #@.
+... ..# ##. .#.
+
+piece Z
+##. ..# ... .#.
+.@# .@# #@. #@.
+... .#. .## #..
+
+piece J
+#.. .## ... .#.
+#@# .@. #@# .@.
+... .#. ..# ##.
+
+piece L
+..# .#. ... ##.
+#@# .@. #@# .@.
+... .## #.. .#.
+";
+/// which piece of the [PieceSet] in play, by its position in the set
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PieceType(pub usize);
+impl PieceType {
+    // positions of the standard tetrominoes in [TETROMINOES]
+    pub const I: PieceType = PieceType(0);
+    pub const O: PieceType = PieceType(1);
+    pub const T: PieceType = PieceType(2);
+    pub const S: PieceType = PieceType(3);
+    pub const Z: PieceType = PieceType(4);
+    pub const J: PieceType = PieceType(5);
+    pub const L: PieceType = PieceType(6);
+}
+/// block offsets for each of the four rotation states (spawn, right, 180, left).
+/// the first offset of every state is the center block which gets drawn bright
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shape {
+    rotations: [[(i32, i32); MAX_PIECE_CELLS]; 4],
+    cell_count: usize,
+}
+impl Shape {
+    pub fn cells(&self, rotation: usize) -> &[(i32, i32)] {
+        &self.rotations[rotation][..self.cell_count]
+    }
+}
+/// which wall kicks a piece tries when its plain rotation doesn't fit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Kicks {
+    /// [JLSTZ_KICKS]
+    #[default]
+    Srs,
+    /// [I_KICKS]
+    SrsI,
+    /// only the plain rotation, for pieces like the O that look the same in every state
+    None,
+}
+/// one of the pieces in a [PieceSet]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceDef {
+    pub name: String,
+    pub shape: Shape,
+    /// columns and rows to move the piece from where it would spawn, horizontally centered in the
+    /// top of the buffer rows
+    pub spawn_offset: (i32, i32),
+    pub kicks: Kicks,
+    /// whether the piece is checked for T-spins, its center block needs to be the middle of a T
+    pub can_t_spin: bool,
+}
+/// the pieces a stage is played with, loaded from a data file so stages and modes can use
+/// tetrominoes, pentominoes or anything else
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceSet {
+    pub pieces: Vec<PieceDef>,
+}
+impl Default for PieceSet {
+    fn default() -> Self {
+        PieceSet::parse(TETROMINOES).unwrap()
+    }
+}
+/// the lines drawing a piece in a piece set data file, by line number, split into the rotation
+/// states
+type Drawing<'a> = Vec<(usize, Vec<&'a str>)>;
+/// a piece set data file that couldn't be read, with the line it went wrong on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PieceSetError {
+    pub line: usize,
+    pub reason: String,
+}
+impl std::fmt::Display for PieceSetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+impl PieceSet {
+    /// read a piece set from its data file. each piece starts with a `piece <name>` line, followed
+    /// by any of these settings:
+    /// - `kicks srs`, `kicks srs-i` or `kicks none` for the wall kicks to use, srs when left out
+    /// - `t-spin` to check the piece for T-spins
+    /// - `spawn <columns> <rows>` to move where the piece spawns
+    ///
+    /// and then a drawing of the piece where `#` is a block, `@` is the center block and `.` is
+    /// empty. either draw the spawn state on its own, and the other states are made by turning it
+    /// clockwise around the center block, or all four states side by side separated by spaces.
+    /// lines starting with `//` are comments
+    pub fn parse(text: &str) -> Result<Self, PieceSetError> {
+        let mut pieces = Vec::new();
+        // the piece being read, with the drawing of its rotation states so far
+        let mut current: Option<(PieceDef, Drawing)> = None;
+        for (line_idx, line) in text.lines().enumerate() {
+            let line_no = line_idx + 1;
+            let error = |reason: &str| PieceSetError {
+                line: line_no,
+                reason: reason.to_string(),
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            if keyword == "piece" {
+                if let Some(finished) = current.take() {
+                    pieces.push(Self::finish_piece(finished, line_no)?);
+                }
+                let name = words.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return Err(error("piece needs a name"));
+                }
+                let piece_def = PieceDef {
+                    name,
+                    shape: Shape {
+                        rotations: [[(0, 0); MAX_PIECE_CELLS]; 4],
+                        cell_count: 0,
+                    },
+                    spawn_offset: (0, 0),
+                    kicks: Kicks::default(),
+                    can_t_spin: false,
+                };
+                current = Some((piece_def, Vec::new()));
+                continue;
+            }
+            let Some((piece_def, drawing)) = current.as_mut() else {
+                return Err(error("expected a `piece <name>` line first"));
+            };
+            match keyword {
+                "kicks" => {
+                    piece_def.kicks = match words.next() {
+                        Some("srs") => Kicks::Srs,
+                        Some("srs-i") => Kicks::SrsI,
+                        Some("none") => Kicks::None,
+                        _ => return Err(error("kicks must be srs, srs-i or none")),
+                    }
+                }
+                "t-spin" => piece_def.can_t_spin = true,
+                "spawn" => {
+                    let mut offset = words.map(|word| word.parse::<i32>());
+                    piece_def.spawn_offset = match (offset.next(), offset.next()) {
+                        (Some(Ok(columns)), Some(Ok(rows))) => (columns, rows),
+                        _ => return Err(error("spawn needs a number of columns and rows")),
+                    }
+                }
+                _ if line
+                    .chars()
+                    .all(|c| matches!(c, '.' | '#' | '@' | ' ' | '\t')) =>
+                {
+                    drawing.push((line_no, line.split_whitespace().collect()));
+                }
+                _ => return Err(error(&format!("unknown setting `{keyword}`"))),
+            }
+        }
+        if let Some(finished) = current.take() {
+            pieces.push(Self::finish_piece(finished, text.lines().count())?);
+        }
+        if pieces.is_empty() {
+            return Err(PieceSetError {
+                line: text.lines().count(),
+                reason: "no pieces in the set".to_string(),
+            });
+        }
+        Ok(Self { pieces })
+    }
+    /// turn the drawing of a piece's rotation states into its [Shape]
+    fn finish_piece(
+        (mut piece_def, drawing): (PieceDef, Drawing),
+        line_no: usize,
+    ) -> Result<PieceDef, PieceSetError> {
+        let error = |line: usize, reason: &str| PieceSetError {
+            line,
+            reason: format!("piece {}: {reason}", piece_def.name),
+        };
+        let state_count = drawing.first().map_or(0, |(_, states)| states.len());
+        if state_count != 1 && state_count != 4 {
+            return Err(error(
+                line_no,
+                "draw either the spawn state or all four rotation states",
+            ));
+        }
+        if let Some((line, _)) = drawing
+            .iter()
+            .find(|(_, states)| states.len() != state_count)
+        {
+            return Err(error(
+                *line,
+                "every line of the drawing needs the same number of states",
+            ));
+        }
+        let mut states: Vec<Vec<(i32, i32)>> = Vec::new();
+        for state_idx in 0..state_count {
+            let mut center = Vec::new();
+            let mut cells = Vec::new();
+            for (y, (_, row)) in drawing.iter().enumerate() {
+                for (x, c) in row[state_idx].chars().enumerate() {
+                    match c {
+                        '@' => center.push((x as i32, y as i32)),
+                        '#' => cells.push((x as i32, y as i32)),
+                        _ => {}
+                    }
+                }
+            }
+            if center.len() != 1 {
+                return Err(error(
+                    line_no,
+                    "every rotation state needs exactly one center block `@`",
+                ));
+            }
+            center.extend(cells);
+            states.push(center);
+        }
+        // turn the spawn state clockwise around its center block for the other states, y grows
+        // downwards
+        while states.len() < 4 {
+            let (center_x, center_y) = states[0][0];
+            let turned = states
+                .last()
+                .unwrap()
+                .iter()
+                .map(|(x, y)| (center_x - (y - center_y), center_y + (x - center_x)))
+                .collect();
+            states.push(turned);
+        }
+        let cell_count = states[0].len();
+        if cell_count > MAX_PIECE_CELLS {
+            return Err(error(
+                line_no,
+                &format!("pieces can have at most {MAX_PIECE_CELLS} blocks"),
+            ));
+        }
+        if states.iter().any(|cells| cells.len() != cell_count) {
+            return Err(error(
+                line_no,
+                "every rotation state needs the same number of blocks",
+            ));
+        }
+        for (rotation, cells) in states.iter().enumerate() {
+            piece_def.shape.rotations[rotation][..cell_count].copy_from_slice(cells);
+        }
+        piece_def.shape.cell_count = cell_count;
+        Ok(piece_def)
+    }
+    pub fn get(&self, piece_type: PieceType) -> &PieceDef {
+        &self.pieces[piece_type.0]
+    }
+    /// create a piece horizontally centered in the buffer rows above a grid `grid_width` columns
+    /// wide
+    pub fn spawn(&self, piece_type: PieceType, grid_width: i32) -> Piece {
+        let piece_def = self.get(piece_type);
+        let width = piece_def
+            .shape
+            .cells(0)
+            .iter()
+            .map(|(x, _)| *x)
+            .max()
+            .unwrap()
+            + 1;
+        Piece {
+            piece_type,
+            shape: piece_def.shape,
+            x: (grid_width - width) / 2 + piece_def.spawn_offset.0,
+            y: -BUFFER_ROWS + piece_def.spawn_offset.1,
+            rotation: 0,
+        }
+    }
+}
+/// the piece the player is currently moving, positioned by the top left corner of its shape
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Piece {
+    pub piece_type: PieceType,
+    pub shape: Shape,
+    pub x: i32,
+    pub y: i32,
+    pub rotation: usize,
+}
+impl Piece {
+    /// grid coordinates of each block, the first one being the center block
+    pub fn blocks(&self) -> Vec<(i32, i32)> {
+        self.shape
+            .cells(self.rotation)
+            .iter()
+            .map(|(x, y)| (self.x + x, self.y + y))
+            .collect()
+    }
+    pub fn moved(&self, dx: i32, dy: i32) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..*self
+        }
+    }
+    pub fn rotated(&self, clockwise: bool) -> Self {
+        let rotation = if clockwise {
+            self.rotation + 1
+        } else {
+            self.rotation + 3
+        };
+        Self {
+            rotation: rotation % 4,
+            ..*self
+        }
+    }
+}
+/// SRS wall kick offsets for the J, L, S, T and Z pieces, tried in order until the rotated piece
+/// fits. rows are the rotation transitions 0->R, R->0, R->2, 2->R, 2->L, L->2, L->0, 0->L and the
+/// offsets are in grid coordinates, so a positive y moves the piece down
+pub const JLSTZ_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+];
+/// SRS wall kick offsets for the I piece, laid out like [JLSTZ_KICKS]
+pub const I_KICKS: [[(i32, i32); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+];
+/// the offsets to try, in order, when rotating a piece out of the `from` rotation state
+pub fn wall_kicks(kicks: Kicks, from: usize, clockwise: bool) -> &'static [(i32, i32)] {
+    let transition = if clockwise {
+        from * 2
+    } else {
+        (from * 2 + 7) % 8
+    };
+    match kicks {
+        Kicks::None => &[(0, 0)],
+        Kicks::SrsI => &I_KICKS[transition],
+        Kicks::Srs => &JLSTZ_KICKS[transition],
+    }
+}
+/// what the player asked for this frame, decoupled from keyboard and touch handling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// held down this frame, repeats after [Settings::das_ms]
+    MoveLeft,
+    /// held down this frame, repeats after [Settings::das_ms]
+    MoveRight,
+    /// held down this frame, repeats every [Settings::soft_drop_ms]
+    SoftDrop,
+    /// fall one row every [DROP_DOWN_CYCLE_TIME] and lock as soon as the piece lands
+    FirmDrop,
+    /// drop the piece straight onto the stack and lock it in the same frame
+    HardDrop,
+    RotateClockwise,
+    RotateCounterClockwise,
+    /// swap the active piece with the one in the hold slot
+    Hold,
+}
+/// a T piece locked after being rotated into a slot with at least three of the corners around its
+/// center filled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TSpin {
+    /// only one of the two corners the T points at is filled and the rotation didn't need the last
+    /// kick
+    Mini,
+    Full,
+}
+/// columns a [Hazard::Gust] tries to blow the active piece
+pub const GUST_COLUMNS: i32 = 2;
+/// seconds gravity stays doubled after a [Hazard::Haste]
+pub const HASTE_TIME: f32 = 5.;
+/// seconds the player's input is ignored after a [Hazard::TimeStop]
+pub const TIME_STOP_TIME: f32 = 2.;
+/// rows a block rises after a [Hazard::Bubble]
+pub const BUBBLE_RISE: i32 = 2;
+/// locks before a block set alight by [Hazard::Ignite] burns away
+pub const BURN_LOCKS: u8 = 3;
+/// locks before a bomb planted by [Hazard::Bomb] goes off
+pub const BOMB_FUSE_LOCKS: u8 = 3;
+/// board gimmicks the robots use against the player, each one themed on a robot master
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hazard {
+    /// Air Man: blow the active piece sideways
+    Gust,
+    /// Quick Man: double gravity for [HASTE_TIME]
+    Haste,
+    /// Flash Man: ignore the player's input for [TIME_STOP_TIME] while gravity carries on
+    TimeStop,
+    /// Wood Man: drop a leaf block onto the top of a column
+    Leaf,
+    /// Metal Man: knock a single block out of the stack
+    Blade,
+    /// Heat Man: set a block alight so it burns away
+    Ignite,
+    /// Bubble Man: float the top block of a column upwards
+    Bubble,
+    /// Crash Man: plant a bomb in the stack that goes off after a few locks
+    Bomb,
+}
+/// units of energy a weapon holds when full, like the meters in the original game's weapon menu
+pub const WEAPON_ENERGY_MAX: u8 = 28;
+/// units of energy the selected weapon gains for each row cleared
+pub const ENERGY_PER_ROW: u8 = 2;
+/// seconds gravity stays paused after the [Ability::TimeStopper]
+pub const TIME_STOPPER_TIME: f32 = 5.;
+/// the special abilities of the weapons Mega Man takes from the robot masters, powered by the
+/// weapon's energy meter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ability {
+    /// clear the bottom two rows
+    AtomicFire,
+    /// blow away the highest row of the stack
+    AirShooter,
+    /// fill in the lowest hole in each column
+    BubbleLead,
+    /// cancel the garbage that hasn't arrived yet
+    QuickBoomerang,
+    /// pause gravity for [TIME_STOPPER_TIME]
+    TimeStopper,
+    /// remove the column under the active piece
+    MetalBlade,
+    /// clear a 3x3 area where the active piece would land
+    CrashBomb,
+    /// let the next piece to lock pass through the stack and settle as low as it fits
+    LeafShield,
+}
+impl Ability {
+    pub const ALL: [Ability; 8] = [
+        Ability::AtomicFire,
+        Ability::AirShooter,
+        Ability::BubbleLead,
+        Ability::QuickBoomerang,
+        Ability::TimeStopper,
+        Ability::MetalBlade,
+        Ability::CrashBomb,
+        Ability::LeafShield,
+    ];
+    /// units of weapon energy used up each time the ability is used
+    pub fn energy_cost(self) -> u8 {
+        match self {
+            Ability::AtomicFire => 14,
+            Ability::TimeStopper => WEAPON_ENERGY_MAX,
+            Ability::QuickBoomerang => 4,
+            Ability::AirShooter
+            | Ability::BubbleLead
+            | Ability::MetalBlade
+            | Ability::CrashBomb
+            | Ability::LeafShield => 7,
+        }
+    }
+    fn idx(self) -> usize {
+        Ability::ALL
+            .iter()
+            .position(|ability| *ability == self)
+            .unwrap()
+    }
+}
+/// ways the stack can get too high to keep playing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopOut {
+    /// a new piece overlapped the stack where it spawned
+    BlockOut,
+    /// a piece locked with some of its blocks left above the visible grid, where they'd be out
+    /// of sight
+    LockOut,
+    /// garbage rows pushed the stack up past the buffer rows
+    GarbageOut,
+}
+/// things that happened during an [Engine::update] that the gameplay state may want to react to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    PieceSpawned(PieceType),
+    PieceLocked,
+    /// the piece about to lock was a T-spin, sent just before its [Event::PieceLocked]
+    TSpin(TSpin),
+    /// the active piece was hard dropped this many rows, a [Event::PieceLocked] follows it
+    HardDropped(i32),
+    /// the active piece was moved into the hold slot
+    PieceHeld(PieceType),
+    /// completed rows were found and set to flashing
+    RowsFlagged(u8),
+    /// a flashing row was removed and the rows above it moved down
+    RowCleared(i32),
+    /// the last flashing row was removed and left the grid completely empty
+    PerfectClear,
+    /// garbage rows were pushed in under the stack
+    GarbageAdded(u32),
+    /// a robot used its board gimmick
+    Hazard(Hazard),
+    /// a bomb went off at these grid coordinates
+    BombDetonated(i32, i32),
+    /// the player used a weapon's special ability
+    AbilityUsed(Ability),
+    /// blocks falling after a clear completed more rows, reported with the chain depth ahead of the
+    /// [Event::RowsFlagged] for the rows
+    Chain(u32),
+    /// the game is over, see [TopOut] for why
+    TopOut(TopOut),
+}
+/// source of randomness for a [PieceGenerator], swap in another one to control the piece order
+pub trait PieceRng: Clone + std::fmt::Debug {
+    fn next_u32(&mut self) -> u32;
+}
+/// small seeded xorshift generator so that a stage can be reproduced exactly from its seed
+#[derive(Clone, Debug)]
+pub struct XorShift64 {
+    state: u64,
+}
+impl XorShift64 {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero so swap it for an arbitrary odd constant
+        Self {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+}
+impl PieceRng for XorShift64 {
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u32
+    }
+}
+/// how the [PieceGenerator] picks the next piece
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Randomizer {
+    /// deal every piece in the set, seven for the tetrominoes, from a shuffled bag before refilling
+    /// it
+    #[default]
+    SevenBag,
+    /// every piece is picked independently, like the original `TetrisPiece::new()`
+    Random,
+    /// NES style: roll one more time when the first roll repeats the previous piece
+    NesReroll,
+}
+/// what happens to the blocks above a row once it's cleared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClearGravity {
+    /// every row above moves down by one, leaving overhangs floating like the original game
+    #[default]
+    Naive,
+    /// connected groups of blocks fall as units until they land, which can complete more rows and
+    /// set off a chain
+    Cascade,
+}
+#[derive(Clone, Debug)]
+pub struct PieceGenerator<R: PieceRng = XorShift64> {
+    pub randomizer: Randomizer,
+    pub seed: u64,
+    /// number of pieces in the [PieceSet] being dealt from
+    pub piece_count: usize,
+    rng: R,
+    bag: Vec<PieceType>,
+    last_piece_type: Option<PieceType>,
+}
+impl PieceGenerator {
+    pub fn new(randomizer: Randomizer, piece_count: usize, seed: u64) -> Self {
+        Self::with_rng(randomizer, piece_count, seed, XorShift64::new(seed))
+    }
+}
+impl<R: PieceRng> PieceGenerator<R> {
+    /// create a generator that draws from the given rng, `seed` is only recorded
+    pub fn with_rng(randomizer: Randomizer, piece_count: usize, seed: u64, rng: R) -> Self {
+        Self {
+            randomizer,
+            seed,
+            piece_count,
+            rng,
+            bag: Vec::new(),
+            last_piece_type: None,
+        }
+    }
+    pub fn next_piece_type(&mut self) -> PieceType {
+        let piece_type = match self.randomizer {
+            Randomizer::SevenBag => {
+                if self.bag.is_empty() {
+                    self.bag = (0..self.piece_count).map(PieceType).collect();
+                    // fisher-yates shuffle
+                    for idx in (1..self.bag.len()).rev() {
+                        let swap_idx = self.roll(idx as u32 + 1) as usize;
+                        self.bag.swap(idx, swap_idx);
+                    }
+                }
+                self.bag.pop().unwrap()
+            }
+            Randomizer::Random => PieceType(self.roll(self.piece_count as u32) as usize),
+            Randomizer::NesReroll => {
+                // the extra roll stands in for the NES's unused piece id and also forces a reroll
+                let first_roll = self.roll(self.piece_count as u32 + 1) as usize;
+                if first_roll == self.piece_count
+                    || Some(PieceType(first_roll)) == self.last_piece_type
+                {
+                    PieceType(self.roll(self.piece_count as u32) as usize)
+                } else {
+                    PieceType(first_roll)
+                }
+            }
+        };
+        self.last_piece_type = Some(piece_type);
+        piece_type
+    }
+    fn roll(&mut self, sides: u32) -> u32 {
+        self.rng.next_u32() % sides
+    }
+}
+/// how gravity speeds up over a fight. each term takes time off [DROP_DOWN_SPEED], which never goes
+/// below `min_drop_down_speed`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GravityCurve {
+    pub min_drop_down_speed: f32,
+    /// seconds taken off for every row cleared
+    pub per_row_cleared: f32,
+    /// seconds taken off for every minute spent in the stage
+    pub per_minute: f32,
+    /// seconds taken off for every hitpoint the robot has lost
+    pub per_hitpoint_lost: f32,
+}
+impl Default for GravityCurve {
+    /// gravity that stays at [DROP_DOWN_SPEED] for the whole fight
+    fn default() -> Self {
+        Self {
+            min_drop_down_speed: DROP_DOWN_SPEED,
+            per_row_cleared: 0.,
+            per_minute: 0.,
+            per_hitpoint_lost: 0.,
+        }
+    }
+}
+impl GravityCurve {
+    /// time in seconds for the active piece to move down one row at this point in the fight
+    pub fn drop_down_speed(&self, rows_cleared: u32, time_elapsed: f32, hitpoints_lost: u8) -> f32 {
+        let speed_up = self.per_row_cleared * rows_cleared as f32
+            + self.per_minute * time_elapsed / 60.
+            + self.per_hitpoint_lost * hitpoints_lost as f32;
+        (DROP_DOWN_SPEED - speed_up).max(self.min_drop_down_speed.min(DROP_DOWN_SPEED))
+    }
+}
+/// fewest upcoming pieces shown beside the grid
+pub const MIN_PREVIEW_COUNT: usize = 1;
+/// most upcoming pieces shown beside the grid
+pub const MAX_PREVIEW_COUNT: usize = 5;
+/// player facing options for the puzzle rules
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub randomizer: Randomizer,
+    /// number of upcoming pieces to show, clamped between [MIN_PREVIEW_COUNT] and
+    /// [MAX_PREVIEW_COUNT]
+    pub preview_count: usize,
+    /// draw where the active piece would land
+    pub show_ghost: bool,
+    /// size of the grid, only read when the [Engine] is created
+    pub dimensions: Dimensions,
+    /// delayed auto shift: milliseconds left or right must be held before the piece starts sliding
+    pub das_ms: u32,
+    /// auto repeat rate: milliseconds between each step of the slide, 0 slides straight to the wall
+    pub arr_ms: u32,
+    /// milliseconds between each step while soft dropping
+    pub soft_drop_ms: u32,
+    pub clear_gravity: ClearGravity,
+    /// pieces to play with, only read when the [Engine] is created
+    pub piece_set: PieceSet,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            randomizer: Randomizer::default(),
+            preview_count: 3,
+            show_ghost: true,
+            dimensions: Dimensions::default(),
+            das_ms: 170,
+            arr_ms: 50,
+            soft_drop_ms: 50,
+            clear_gravity: ClearGravity::default(),
+            piece_set: PieceSet::default(),
+        }
+    }
+}
+#[derive(Clone, Debug)]
+pub struct Engine<R: PieceRng = XorShift64> {
+    pub settings: Settings,
+    /// grid points in row major order
+    pub matrix: Vec<Cell>,
+    pub piece: Option<Piece>,
+    pub generator: PieceGenerator<R>,
+    /// pieces already drawn from the generator, the next one to spawn first
+    pub next_piece_types: VecDeque<PieceType>,
+    /// piece set aside by the player to bring back later
+    pub held_piece_type: Option<PieceType>,
+    /// hold can only be used once per piece, so this is cleared on hold and set again on lock
+    pub can_hold: bool,
+    /// time in seconds for the active piece to move down one row, see [GravityCurve]
+    pub drop_down_speed: f32,
+    /// time since the active piece last moved down
+    pub turn_time_elapsed: f32,
+    /// time the active piece has spent resting on the stack since its lock delay last restarted
+    pub lock_time_elapsed: f32,
+    /// times the lock delay has been restarted since the active piece reached its lowest row
+    pub lock_resets: u32,
+    /// lowest row the active piece has reached, moving below it gives back all the lock resets
+    pub lowest_y: i32,
+    /// which wall kick the last successful rotation used, `None` once the piece has moved since
+    pub last_kick_idx: Option<usize>,
+    /// time since a piece locked or a row was flagged or removed
+    pub grid_time_elapsed: f32,
+    pub is_topped_out: bool,
+    /// rows removed from the grid so far
+    pub rows_cleared: u32,
+    /// locks in a row that have each cleared at least one row, 0 once a lock clears nothing
+    pub combo: u32,
+    /// the last clear was difficult (four rows or a t-spin) and so was the one before it
+    pub is_back_to_back: bool,
+    /// the last clear was difficult, so the next difficult one continues a back-to-back chain
+    pub was_last_clear_difficult: bool,
+    /// game time spent in the stage so far
+    pub time_elapsed: f32,
+    /// direction left or right is being held in, -1, 0 or 1
+    pub shift_direction: i32,
+    /// time left or right has been held in [Engine::shift_direction]
+    pub shift_time_elapsed: f32,
+    /// time soft drop has been held, `None` when it isn't
+    pub soft_drop_time_elapsed: Option<f32>,
+    /// garbage rows waiting to be pushed in under the stack the next time a piece locks without a
+    /// clear
+    pub pending_garbage: u32,
+    /// attacks queued with [Engine::queue_garbage] so far
+    pub attacks_received: u32,
+    /// hazards triggered with [Engine::trigger_hazard] so far
+    pub hazards_triggered: u32,
+    /// time left on a [Hazard::Haste]
+    pub haste_time_remaining: f32,
+    /// time left on a [Hazard::TimeStop]
+    pub time_stop_remaining: f32,
+    /// picks the holes in garbage and where hazards land, seeded apart from the pieces so attacks
+    /// don't change the piece order
+    hazard_rng: XorShift64,
+    /// the ability of the weapon Mega Man has selected, the one that gains energy from cleared rows
+    pub selected_ability: Option<Ability>,
+    /// energy left in each weapon's meter, in the order of [Ability::ALL]
+    pub ability_energy: [u8; Ability::ALL.len()],
+    /// time left on the [Ability::TimeStopper]
+    pub gravity_stop_remaining: f32,
+    /// the next piece to lock passes through the stack, see [Ability::LeafShield]
+    pub is_leaf_shielded: bool,
+    /// clears set off by [ClearGravity::Cascade] since the last piece locked
+    pub chain: u32,
+}
+impl Engine {
+    /// create an engine dealing pieces from the given seed with the randomizer picked in the
+    /// settings
+    pub fn new(settings: Settings, seed: u64) -> Self {
+        let generator =
+            PieceGenerator::new(settings.randomizer, settings.piece_set.pieces.len(), seed);
+        Self::with_generator(settings, generator)
+    }
+}
+impl<R: PieceRng> Engine<R> {
+    pub fn with_generator(settings: Settings, generator: PieceGenerator<R>) -> Self {
+        let hazard_rng = XorShift64::new(!generator.seed);
+        let matrix_len = settings.dimensions.width * (BUFFER_ROWS + settings.dimensions.height);
+        let mut engine = Self {
+            settings,
+            matrix: vec![Cell::Empty; matrix_len as usize],
+            piece: None,
+            generator,
+            next_piece_types: VecDeque::new(),
+            held_piece_type: None,
+            can_hold: true,
+            drop_down_speed: DROP_DOWN_SPEED,
+            turn_time_elapsed: 0.,
+            lock_time_elapsed: 0.,
+            lock_resets: 0,
+            lowest_y: 0,
+            last_kick_idx: None,
+            grid_time_elapsed: 0.,
+            is_topped_out: false,
+            rows_cleared: 0,
+            combo: 0,
+            is_back_to_back: false,
+            was_last_clear_difficult: false,
+            time_elapsed: 0.,
+            shift_direction: 0,
+            shift_time_elapsed: 0.,
+            soft_drop_time_elapsed: None,
+            pending_garbage: 0,
+            attacks_received: 0,
+            hazards_triggered: 0,
+            haste_time_remaining: 0.,
+            time_stop_remaining: 0.,
+            hazard_rng,
+            selected_ability: None,
+            ability_energy: [0; Ability::ALL.len()],
+            gravity_stop_remaining: 0.,
+            is_leaf_shielded: false,
+            chain: 0,
+        };
+        engine.fill_next_piece_types();
+        engine
+    }
+    /// the upcoming pieces to show beside the grid, next one first
+    pub fn preview(&self) -> impl Iterator<Item = PieceType> + '_ {
+        self.next_piece_types
+            .iter()
+            .copied()
+            .take(self.preview_count())
+    }
+    fn preview_count(&self) -> usize {
+        self.settings
+            .preview_count
+            .clamp(MIN_PREVIEW_COUNT, MAX_PREVIEW_COUNT)
+    }
+    /// top up the upcoming pieces. pieces already drawn are never thrown away so the piece order
+    /// doesn't depend on how many are being previewed
+    fn fill_next_piece_types(&mut self) {
+        while self.next_piece_types.len() < self.preview_count() {
+            let piece_type = self.generator.next_piece_type();
+            self.next_piece_types.push_back(piece_type);
+        }
+    }
+    fn take_next_piece_type(&mut self) -> PieceType {
+        let piece_type = self.next_piece_types.pop_front().unwrap();
+        self.fill_next_piece_types();
+        piece_type
+    }
+    /// grid point at the given coordinates, anything outside the grid and its buffer rows counts as
+    /// filled
+    pub fn cell(&self, x: i32, y: i32) -> Cell {
+        if x < 0 || x >= self.width() || y < -BUFFER_ROWS || y >= self.height() {
+            return Cell::Active;
+        }
+        self.matrix[self.matrix_idx(x, y)]
+    }
+    /// the grid points that are drawn, leaving out the buffer rows
+    pub fn visible_matrix(&self) -> &[Cell] {
+        &self.matrix[self.matrix_idx(0, 0)..]
+    }
+    /// number of columns in the grid
+    pub fn width(&self) -> i32 {
+        self.settings.dimensions.width
+    }
+    /// number of visible rows in the grid
+    pub fn height(&self) -> i32 {
+        self.settings.dimensions.height
+    }
+    /// index into [Engine::matrix] for grid coordinates, which includes the buffer rows
+    fn matrix_idx(&self, x: i32, y: i32) -> usize {
+        ((y + BUFFER_ROWS) * self.width() + x) as usize
+    }
+    /// check if every block of the piece sits on an empty grid point
+    pub fn fits(&self, piece: &Piece) -> bool {
+        piece
+            .blocks()
+            .iter()
+            .all(|(x, y)| self.cell(*x, *y) == Cell::Empty)
+    }
+    /// check if the active piece can't move down any further
+    pub fn is_resting(&self) -> bool {
+        self.piece
+            .is_some_and(|piece| !self.fits(&piece.moved(0, 1)))
+    }
+    /// the active piece dropped as far down as it can go, or nothing if the ghost is turned off
+    pub fn ghost(&self) -> Option<Piece> {
+        self.piece
+            .filter(|_| self.settings.show_ghost)
+            .map(|piece| self.dropped(piece))
+    }
+    /// the piece moved down until it rests on the stack or the bottom of the grid
+    pub fn dropped(&self, mut piece: Piece) -> Piece {
+        while self.fits(&piece.moved(0, 1)) {
+            piece = piece.moved(0, 1);
+        }
+        piece
+    }
+    pub fn has_flashing_rows(&self) -> bool {
+        self.matrix.contains(&Cell::Flashing)
+    }
+    /// advance the rules by `dt` seconds after applying the inputs for this frame
+    pub fn update(&mut self, inputs: &[Input], dt: f32) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.is_topped_out {
+            return events;
+        }
+        self.time_elapsed += dt;
+        self.turn_time_elapsed += dt;
+        self.grid_time_elapsed += dt;
+        self.haste_time_remaining = (self.haste_time_remaining - dt).max(0.);
+        self.time_stop_remaining = (self.time_stop_remaining - dt).max(0.);
+        self.gravity_stop_remaining = (self.gravity_stop_remaining - dt).max(0.);
+        // the lock delay only runs while the piece rests on the stack, so there's still a moment to
+        // slide or tuck it in before it locks
+        if self.is_resting() {
+            self.lock_time_elapsed += dt;
+            if self.lock_time_elapsed >= LOCK_DELAY {
+                self.lock_piece(&mut events);
+            }
+        }
+        if self.is_topped_out {
+            return events;
+        }
+        // if there is not an active piece, insert one once enough time has passed since the last
+        // lock or clear
+        if self.piece.is_none() && self.grid_time_elapsed > DROP_DOWN_SPEED {
+            self.grid_time_elapsed = 0.;
+            let piece_type = self.take_next_piece_type();
+            self.spawn_piece(piece_type, &mut events);
+        }
+        // time stopped, the player can't do anything but watch the piece fall
+        let inputs = if self.time_stop_remaining > 0. {
+            &[]
+        } else {
+            inputs
+        };
+        self.apply_held_inputs(inputs, dt);
+        for input in inputs {
+            self.apply_input(*input, &mut events);
+        }
+        if self.is_topped_out {
+            return events;
+        }
+        // no input - let gravity move the active piece down, unless the time stopper is holding it
+        if self.gravity_stop_remaining <= 0.
+            && self.turn_time_elapsed >= self.gravity_speed()
+            && self.try_move(0, 1)
+        {
+            self.turn_time_elapsed = 0.;
+        }
+        self.clear_lines(&mut events);
+        events
+    }
+    /// time for the active piece to move down a row, [Engine::drop_down_speed] halved during a
+    /// [Hazard::Haste]
+    fn gravity_speed(&self) -> f32 {
+        if self.haste_time_remaining > 0. {
+            self.drop_down_speed / 2.
+        } else {
+            self.drop_down_speed
+        }
+    }
+    /// place a new piece in the buffer rows, topping out if it overlaps the stack. it drops one row
+    /// straight away when it can, so it peeks into the grid
+    fn spawn_piece(&mut self, piece_type: PieceType, events: &mut Vec<Event>) {
+        let mut piece = self.settings.piece_set.spawn(piece_type, self.width());
+        if !self.fits(&piece) {
+            self.piece = None;
+            self.is_topped_out = true;
+            events.push(Event::TopOut(TopOut::BlockOut));
+            return;
+        }
+        if self.fits(&piece.moved(0, 1)) {
+            piece = piece.moved(0, 1);
+        }
+        self.piece = Some(piece);
+        self.turn_time_elapsed = 0.;
+        self.lock_time_elapsed = 0.;
+        self.lock_resets = 0;
+        self.lowest_y = piece.y;
+        self.last_kick_idx = None;
+        events.push(Event::PieceSpawned(piece_type));
+    }
+    /// move the active piece into the hold slot and bring back the held piece, or the next one if
+    /// the slot is still empty
+    fn hold_piece(&mut self, events: &mut Vec<Event>) {
+        let Some(piece) = self.piece.filter(|_| self.can_hold) else {
+            return;
+        };
+        self.can_hold = false;
+        let piece_type = match self.held_piece_type.replace(piece.piece_type) {
+            Some(held_piece_type) => held_piece_type,
+            None => self.take_next_piece_type(),
+        };
+        events.push(Event::PieceHeld(piece.piece_type));
+        self.spawn_piece(piece_type, events);
+    }
+    /// move the piece for the inputs that auto repeat while held. each one moves the piece as soon
+    /// as it is pressed, then again every time a repeat comes due, so the speed depends on game
+    /// time and not on the frame rate
+    fn apply_held_inputs(&mut self, inputs: &[Input], dt: f32) {
+        let shift_direction = match (
+            inputs.contains(&Input::MoveLeft),
+            inputs.contains(&Input::MoveRight),
+        ) {
+            (true, false) => -1,
+            (false, true) => 1,
+            // holding both keeps sliding whichever way was held first
+            (true, true) => self.shift_direction,
+            (false, false) => 0,
+        };
+        if shift_direction != self.shift_direction {
+            self.shift_direction = shift_direction;
+            self.shift_time_elapsed = 0.;
+            if shift_direction != 0 {
+                self.try_move(shift_direction, 0);
+            }
+        } else if shift_direction != 0 {
+            let das = self.settings.das_ms as f32 / 1000.;
+            let arr = self.settings.arr_ms as f32 / 1000.;
+            let width = self.width();
+            let repeats = |time_elapsed: f32| {
+                if time_elapsed < das {
+                    0
+                } else if arr <= 0. {
+                    width
+                } else {
+                    ((time_elapsed - das) / arr) as i32 + 1
+                }
+            };
+            let previous_repeats = repeats(self.shift_time_elapsed);
+            self.shift_time_elapsed += dt;
+            for _ in previous_repeats..repeats(self.shift_time_elapsed) {
+                if !self.try_move(shift_direction, 0) {
+                    break;
+                }
+            }
+        }
+        if !inputs.contains(&Input::SoftDrop) {
+            self.soft_drop_time_elapsed = None;
+            return;
+        }
+        let (previous_steps, steps) = match self.soft_drop_time_elapsed {
+            None => {
+                self.soft_drop_time_elapsed = Some(0.);
+                (0, 1)
+            }
+            Some(time_elapsed) => {
+                let rate = self.settings.soft_drop_ms.max(1) as f32 / 1000.;
+                self.soft_drop_time_elapsed = Some(time_elapsed + dt);
+                (
+                    (time_elapsed / rate) as i32,
+                    ((time_elapsed + dt) / rate) as i32,
+                )
+            }
+        };
+        // if you press down to accelerate the block, reset the drop down timer
+        for _ in previous_steps..steps {
+            if !self.try_move(0, 1) {
+                break;
+            }
+            self.turn_time_elapsed = 0.;
+        }
+    }
+    fn apply_input(&mut self, input: Input, events: &mut Vec<Event>) {
+        if self.is_topped_out {
+            return;
+        }
+        match input {
+            // held inputs are handled by apply_held_inputs
+            Input::MoveLeft | Input::MoveRight | Input::SoftDrop => {}
+            // firm dropping onto the stack locks straight away, like it did before the lock delay
+            Input::FirmDrop => {
+                if self.is_resting() {
+                    self.lock_time_elapsed = LOCK_DELAY;
+                } else if self.piece.is_some() && self.turn_time_elapsed >= DROP_DOWN_CYCLE_TIME {
+                    self.turn_time_elapsed = self.gravity_speed();
+                }
+            }
+            Input::HardDrop => {
+                if let Some(piece) = self.piece {
+                    let dropped = self.dropped(piece);
+                    self.piece = Some(dropped);
+                    if dropped.y != piece.y {
+                        self.last_kick_idx = None;
+                    }
+                    events.push(Event::HardDropped(dropped.y - piece.y));
+                    self.lock_piece(events);
+                }
+            }
+            Input::RotateClockwise => {
+                self.try_rotate(true);
+            }
+            Input::RotateCounterClockwise => {
+                self.try_rotate(false);
+            }
+            Input::Hold => self.hold_piece(events),
+        }
+    }
+    fn try_move(&mut self, dx: i32, dy: i32) -> bool {
+        match self.piece {
+            Some(piece) if self.fits(&piece.moved(dx, dy)) => {
+                self.set_piece(piece.moved(dx, dy));
+                self.last_kick_idx = None;
+                true
+            }
+            _ => false,
+        }
+    }
+    /// rotate the active piece, kicking it off walls and the stack when the plain rotation doesn't
+    /// fit
+    fn try_rotate(&mut self, clockwise: bool) -> bool {
+        let Some(piece) = self.piece else {
+            return false;
+        };
+        let rotated = piece.rotated(clockwise);
+        let kicks = self.settings.piece_set.get(piece.piece_type).kicks;
+        match wall_kicks(kicks, piece.rotation, clockwise)
+            .iter()
+            .map(|(dx, dy)| rotated.moved(*dx, *dy))
+            .enumerate()
+            .find(|(_, kicked)| self.fits(kicked))
+        {
+            Some((kick_idx, kicked)) => {
+                self.set_piece(kicked);
+                self.last_kick_idx = Some(kick_idx);
+                true
+            }
+            None => false,
+        }
+    }
+    /// replace the active piece after a successful move or rotation and keep the lock delay up to
+    /// date
+    fn set_piece(&mut self, piece: Piece) {
+        self.piece = Some(piece);
+        if piece.y > self.lowest_y {
+            self.lowest_y = piece.y;
+            self.lock_time_elapsed = 0.;
+            self.lock_resets = 0;
+        } else if self.lock_time_elapsed > 0. && self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_time_elapsed = 0.;
+            self.lock_resets += 1;
+        }
+    }
+    /// check the active piece for a T-spin using the 3 corner rule, if its piece set allows it. the
+    /// two corners the T points at decide between a full T-spin and a mini, unless the rotation
+    /// needed the last wall kick
+    pub fn t_spin(&self) -> Option<TSpin> {
+        let piece = self
+            .piece
+            .filter(|piece| self.settings.piece_set.get(piece.piece_type).can_t_spin)?;
+        let kick_idx = self.last_kick_idx?;
+        let (center_x, center_y) = piece.blocks()[0];
+        // clockwise from the top left, so the T points at corners `rotation` and `rotation + 1`
+        let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)]
+            .map(|(dx, dy)| self.cell(center_x + dx, center_y + dy) != Cell::Empty);
+        if corners.iter().filter(|is_filled| **is_filled).count() < 3 {
+            return None;
+        }
+        if (corners[piece.rotation] && corners[(piece.rotation + 1) % 4]) || kick_idx == 4 {
+            Some(TSpin::Full)
+        } else {
+            Some(TSpin::Mini)
+        }
+    }
+    /// write the active piece into the grid and remove it so a new one can be created
+    fn lock_piece(&mut self, events: &mut Vec<Event>) {
+        let t_spin = self.t_spin();
+        if let Some(t_spin) = t_spin {
+            events.push(Event::TSpin(t_spin));
+        }
+        if let Some(mut piece) = self.piece.take() {
+            if self.is_leaf_shielded {
+                self.is_leaf_shielded = false;
+                piece = (piece.y..self.height())
+                    .map(|y| Piece { y, ..piece })
+                    .filter(|sunk| self.fits(sunk))
+                    .last()
+                    .unwrap_or(piece);
+            }
+            for (x, y) in piece.blocks() {
+                let idx = self.matrix_idx(x, y);
+                self.matrix[idx] = Cell::Active;
+            }
+            self.grid_time_elapsed = 0.;
+            self.can_hold = true;
+            self.chain = 0;
+            self.burn_fuses(events);
+            // the piece's blocks in the buffer rows come down a row for each row below them it
+            // completes, any still above the grid after that would be stuck out of sight
+            let completed: Vec<i32> = (-BUFFER_ROWS..self.height())
+                .filter(|y| (0..self.width()).all(|x| self.cell(x, *y).is_solid()))
+                .collect();
+            let is_locked_out = piece.blocks().iter().any(|(_, y)| {
+                let rows_below = completed.iter().filter(|row| *row > y).count() as i32;
+                !completed.contains(y) && y + rows_below < 0
+            });
+            let completed_rows = self.update_chains(t_spin);
+            events.push(Event::PieceLocked);
+            // clearing rows cancels out garbage that hasn't arrived yet
+            if completed_rows > 0 {
+                self.charge_ability(completed_rows);
+                self.pending_garbage = self.pending_garbage.saturating_sub(completed_rows);
+            } else {
+                self.add_garbage(events);
+            }
+            // the game only ends once, garbage pushing the stack out counts before the piece
+            // locking out
+            if !self.is_topped_out && is_locked_out {
+                self.is_topped_out = true;
+                events.push(Event::TopOut(TopOut::LockOut));
+            }
+        }
+    }
+    /// keep the combo and back-to-back chains going if the piece that just locked completed any
+    /// rows, before they start flashing returns the number of completed rows
+    fn update_chains(&mut self, t_spin: Option<TSpin>) -> u32 {
+        let completed_rows = (-BUFFER_ROWS..self.height())
+            .filter(|y| (0..self.width()).all(|x| self.cell(x, *y).is_solid()))
+            .count() as u32;
+        if completed_rows == 0 {
+            self.combo = 0;
+            return 0;
+        }
+        self.combo += 1;
+        let is_difficult = completed_rows >= 4 || t_spin.is_some();
+        self.is_back_to_back = is_difficult && self.was_last_clear_difficult;
+        self.was_last_clear_difficult = is_difficult;
+        completed_rows
+    }
+    /// count down the burning blocks and bombs by one lock, removing the burnt out blocks and
+    /// setting off the bombs whose fuses have run out
+    fn burn_fuses(&mut self, events: &mut Vec<Event>) {
+        let mut detonations = Vec::new();
+        for idx in 0..self.matrix.len() {
+            self.matrix[idx] = match self.matrix[idx] {
+                Cell::Burning(1) => Cell::Empty,
+                Cell::Burning(locks) => Cell::Burning(locks - 1),
+                Cell::Bomb(1) => {
+                    detonations.push(idx);
+                    Cell::Empty
+                }
+                Cell::Bomb(locks) => Cell::Bomb(locks - 1),
+                cell => cell,
+            };
+        }
+        let width = self.width();
+        for idx in detonations {
+            let (x, y) = (idx as i32 % width, idx as i32 / width - BUFFER_ROWS);
+            self.blast(x, y);
+            events.push(Event::BombDetonated(x, y));
+        }
+    }
+    /// blow away the solid blocks in the 3x3 area around the given grid coordinates, rows already
+    /// flashing stay to be cleared
+    fn blast(&mut self, x: i32, y: i32) {
+        for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+            if self.cell(x + dx, y + dy).is_solid() && self.is_in_matrix(x + dx, y + dy) {
+                let blast_idx = self.matrix_idx(x + dx, y + dy);
+                self.matrix[blast_idx] = Cell::Empty;
+            }
+        }
+    }
+    /// fill the selected weapon's energy meter for the rows cleared
+    fn charge_ability(&mut self, completed_rows: u32) {
+        if let Some(ability) = self.selected_ability {
+            let energy = &mut self.ability_energy[ability.idx()];
+            *energy = (*energy as u32 + completed_rows * ENERGY_PER_ROW as u32)
+                .min(WEAPON_ENERGY_MAX as u32) as u8;
+        }
+    }
+    /// energy left in the given ability's weapon meter
+    pub fn ability_energy(&self, ability: Ability) -> u8 {
+        self.ability_energy[ability.idx()]
+    }
+    /// use the selected weapon's special ability if its meter has enough energy, energy is only
+    /// used up when the ability has something to act on
+    pub fn use_ability(&mut self, events: &mut Vec<Event>) {
+        let Some(ability) = self.selected_ability else {
+            return;
+        };
+        if self.is_topped_out || self.ability_energy(ability) < ability.energy_cost() {
+            return;
+        }
+        let is_used = match ability {
+            Ability::AtomicFire => self.flash_rows(self.height() - 2..self.height()),
+            Ability::AirShooter => {
+                let top_row = (-BUFFER_ROWS..self.height())
+                    .find(|y| (0..self.width()).any(|x| self.cell(x, *y).is_solid()));
+                top_row.is_some_and(|y| self.flash_rows(y..y + 1))
+            }
+            Ability::BubbleLead => {
+                let mut is_filled = false;
+                for x in 0..self.width() {
+                    // the lowest empty cell with a block somewhere above it
+                    let top = (-BUFFER_ROWS..self.height()).find(|y| self.cell(x, *y).is_solid());
+                    let hole = top.and_then(|top| {
+                        (top..self.height())
+                            .rev()
+                            .find(|y| self.cell(x, *y) == Cell::Empty)
+                    });
+                    if let Some(y) = hole.filter(|y| !self.is_under_piece(x, *y)) {
+                        let idx = self.matrix_idx(x, y);
+                        self.matrix[idx] = Cell::Active;
+                        is_filled = true;
+                    }
+                }
+                is_filled
+            }
+            Ability::QuickBoomerang => {
+                let is_incoming = self.pending_garbage > 0;
+                self.pending_garbage = 0;
+                is_incoming
+            }
+            Ability::TimeStopper => {
+                self.gravity_stop_remaining = TIME_STOPPER_TIME;
+                true
+            }
+            Ability::MetalBlade => match self.piece {
+                Some(piece) => {
+                    let x = piece.blocks()[0].0;
+                    for y in -BUFFER_ROWS..self.height() {
+                        if self.cell(x, y).is_solid() {
+                            let idx = self.matrix_idx(x, y);
+                            self.matrix[idx] = Cell::Empty;
+                        }
+                    }
+                    true
+                }
+                None => false,
+            },
+            Ability::CrashBomb => match self.piece.map(|piece| self.dropped(piece)) {
+                Some(landed) => {
+                    let (x, y) = landed.blocks()[0];
+                    self.blast(x, y);
+                    true
+                }
+                None => false,
+            },
+            Ability::LeafShield => {
+                let is_unshielded = !self.is_leaf_shielded;
+                self.is_leaf_shielded = true;
+                is_unshielded
+            }
+        };
+        if is_used {
+            self.ability_energy[ability.idx()] -= ability.energy_cost();
+            events.push(Event::AbilityUsed(ability));
+        }
+    }
+    /// set whole rows flashing so they get removed like completed rows, without counting towards
+    /// the combo or damaging the robot. only rows with blocks in them are flashed, and not the rows
+    /// the active piece reaches into as it can't be split. returns whether any rows were flashed
+    fn flash_rows(&mut self, rows: std::ops::Range<i32>) -> bool {
+        let piece_rows = self.piece.map(|piece| {
+            let blocks = piece.blocks();
+            let top = blocks.iter().map(|(_, y)| *y).min().unwrap_or(piece.y);
+            let bottom = blocks.iter().map(|(_, y)| *y).max().unwrap_or(piece.y);
+            top..=bottom
+        });
+        let rows: Vec<i32> = rows
+            .filter(|y| (0..self.width()).any(|x| self.cell(x, *y).is_solid()))
+            .filter(|y| {
+                !piece_rows
+                    .as_ref()
+                    .is_some_and(|piece_rows| piece_rows.contains(y))
+            })
+            .collect();
+        for y in &rows {
+            let row_start = self.matrix_idx(0, *y);
+            let width = self.width() as usize;
+            self.matrix[row_start..row_start + width].fill(Cell::Flashing);
+        }
+        if !rows.is_empty() {
+            self.grid_time_elapsed = 0.;
+        }
+        !rows.is_empty()
+    }
+    fn is_in_matrix(&self, x: i32, y: i32) -> bool {
+        (0..self.width()).contains(&x) && (-BUFFER_ROWS..self.height()).contains(&y)
+    }
+    /// let a robot use its board gimmick
+    pub fn trigger_hazard(&mut self, hazard: Hazard, events: &mut Vec<Event>) {
+        self.hazards_triggered += 1;
+        if self.is_topped_out {
+            return;
+        }
+        match hazard {
+            Hazard::Gust => {
+                let direction = [-1, 1][(self.hazard_rng.next_u32() % 2) as usize];
+                for _ in 0..GUST_COLUMNS {
+                    self.try_move(direction, 0);
+                }
+            }
+            Hazard::Haste => self.haste_time_remaining = HASTE_TIME,
+            Hazard::TimeStop => self.time_stop_remaining = TIME_STOP_TIME,
+            Hazard::Leaf => {
+                let x = self.roll_column();
+                // the leaf lands on the top of the column, as long as the active piece isn't in the
+                // way
+                let y = (-BUFFER_ROWS..self.height())
+                    .take_while(|y| self.cell(x, *y) == Cell::Empty)
+                    .last();
+                if let Some(y) = y.filter(|y| !self.is_under_piece(x, *y)) {
+                    let idx = self.matrix_idx(x, y);
+                    self.matrix[idx] = Cell::Active;
+                }
+            }
+            Hazard::Blade => {
+                if let Some(idx) = self.roll_block(|cell| cell == Cell::Active) {
+                    self.matrix[idx] = Cell::Empty;
+                }
+            }
+            Hazard::Ignite => {
+                if let Some(idx) = self.roll_block(|cell| cell == Cell::Active) {
+                    self.matrix[idx] = Cell::Burning(BURN_LOCKS);
+                }
+            }
+            Hazard::Bubble => {
+                let x = self.roll_column();
+                let Some(y) =
+                    (-BUFFER_ROWS..self.height()).find(|y| self.cell(x, *y) != Cell::Empty)
+                else {
+                    return;
+                };
+                let cell = self.cell(x, y);
+                let risen_y = (1..=BUBBLE_RISE)
+                    .map(|rise| y - rise)
+                    .take_while(|risen_y| {
+                        self.cell(x, *risen_y) == Cell::Empty && !self.is_under_piece(x, *risen_y)
+                    })
+                    .last();
+                if let (true, Some(risen_y)) = (cell.is_solid(), risen_y) {
+                    let idx = self.matrix_idx(x, y);
+                    let risen_idx = self.matrix_idx(x, risen_y);
+                    self.matrix[risen_idx] = cell;
+                    self.matrix[idx] = Cell::Empty;
+                }
+            }
+            Hazard::Bomb => {
+                if let Some(idx) = self.roll_block(|cell| cell == Cell::Active) {
+                    self.matrix[idx] = Cell::Bomb(BOMB_FUSE_LOCKS);
+                }
+            }
+        }
+        events.push(Event::Hazard(hazard));
+    }
+    fn roll_column(&mut self) -> i32 {
+        (self.hazard_rng.next_u32() % self.width() as u32) as i32
+    }
+    /// pick one of the blocks in the matrix that match `filter` at random
+    fn roll_block(&mut self, filter: impl Fn(Cell) -> bool) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.matrix.len())
+            .filter(|idx| filter(self.matrix[*idx]))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[(self.hazard_rng.next_u32() % candidates.len() as u32) as usize])
+    }
+    /// check if the active piece has a block at the given grid coordinates
+    fn is_under_piece(&self, x: i32, y: i32) -> bool {
+        self.piece
+            .is_some_and(|piece| piece.blocks().contains(&(x, y)))
+    }
+    /// line up garbage rows to push in under the stack, they arrive the next time a piece locks
+    /// without clearing anything
+    pub fn queue_garbage(&mut self, rows: u32) {
+        self.pending_garbage += rows;
+        self.attacks_received += 1;
+    }
+    /// push the pending garbage in under the stack, every row filled apart from a hole in the same
+    /// column
+    fn add_garbage(&mut self, events: &mut Vec<Event>) {
+        if self.pending_garbage == 0 || self.is_topped_out {
+            return;
+        }
+        let rows = self
+            .pending_garbage
+            .min((BUFFER_ROWS + self.height()) as u32);
+        self.pending_garbage = 0;
+        let width = self.width() as usize;
+        let shifted_len = rows as usize * width;
+        // anything in the rows that get pushed off the top of the buffer ends the game
+        let is_pushed_out = self.matrix[..shifted_len]
+            .iter()
+            .any(|cell| *cell != Cell::Empty);
+        self.matrix.copy_within(shifted_len.., 0);
+        let hole = (self.hazard_rng.next_u32() % width as u32) as usize;
+        let matrix_len = self.matrix.len();
+        for (idx, cell) in self.matrix[matrix_len - shifted_len..]
+            .iter_mut()
+            .enumerate()
+        {
+            *cell = if idx % width == hole {
+                Cell::Empty
+            } else {
+                Cell::Active
+            };
+        }
+        events.push(Event::GarbageAdded(rows));
+        if is_pushed_out {
+            self.is_topped_out = true;
+            events.push(Event::TopOut(TopOut::GarbageOut));
+        }
+    }
+    /// find lines to clear and make them flash, then once they have flashed long enough remove the
+    /// bottom most flashing line and move the rows above it down
+    fn clear_lines(&mut self, events: &mut Vec<Event>) {
+        self.flag_rows(events);
+        if self.grid_time_elapsed < ROW_FLASH_TIME {
+            return;
+        }
+        if let Some(row) = (-BUFFER_ROWS..self.height())
+            .rev()
+            .find(|y| (0..self.width()).all(|x| self.cell(x, *y) == Cell::Flashing))
+        {
+            let row_start = self.matrix_idx(0, row);
+            let width = self.width() as usize;
+            match self.settings.clear_gravity {
+                ClearGravity::Naive => {
+                    // an active piece above the row moves down with the stack, so nothing shifts
+                    // into it
+                    if self
+                        .piece
+                        .is_some_and(|piece| piece.blocks().iter().all(|(_, y)| *y < row))
+                    {
+                        self.piece = self.piece.map(|piece| piece.moved(0, 1));
+                        self.lowest_y += 1;
+                    }
+                    self.matrix.copy_within(0..row_start, width);
+                    self.matrix[..width].fill(Cell::Empty);
+                }
+                // the blocks above stay put until the last flashing row is gone, then fall together
+                ClearGravity::Cascade => {
+                    self.matrix[row_start..row_start + width].fill(Cell::Empty);
+                }
+            }
+            self.grid_time_elapsed = 0.;
+            self.rows_cleared += 1;
+            events.push(Event::RowCleared(row));
+            if self.settings.clear_gravity == ClearGravity::Cascade
+                && !self.has_flashing_rows()
+                && self.cascade()
+                && self.completed_rows() > 0
+            {
+                self.chain += 1;
+                events.push(Event::Chain(self.chain));
+                self.flag_rows(events);
+            }
+            if self.matrix.iter().all(|cell| *cell == Cell::Empty) {
+                events.push(Event::PerfectClear);
+            }
+        }
+    }
+    fn completed_rows(&self) -> usize {
+        self.matrix
+            .chunks(self.width() as usize)
+            .filter(|row| row.iter().all(|cell| cell.is_solid()))
+            .count()
+    }
+    /// set completed rows flashing, ready to be removed once [ROW_FLASH_TIME] has passed
+    fn flag_rows(&mut self, events: &mut Vec<Event>) {
+        let mut new_rows_cleared: u8 = 0;
+        self.matrix
+            .chunks_mut(self.settings.dimensions.width as usize)
+            .filter(|row| row.iter().all(|cell| cell.is_solid()))
+            .for_each(|row| {
+                new_rows_cleared += 1;
+                row.fill(Cell::Flashing);
+            });
+        if new_rows_cleared > 0 {
+            self.grid_time_elapsed = 0.;
+            events.push(Event::RowsFlagged(new_rows_cleared));
+        }
+    }
+    /// let each connected group of blocks fall as a unit until everything has landed, returns
+    /// whether anything moved. groups land on the active piece rather than falling into it
+    fn cascade(&mut self) -> bool {
+        let width = self.width() as usize;
+        let mut has_fallen = false;
+        loop {
+            let groups = self.connected_groups();
+            let mut has_moved = false;
+            for group in groups {
+                // a group can fall while every cell below it is empty or part of the group
+                let can_fall = group.iter().all(|idx| {
+                    let below = idx + width;
+                    let (x, y) = ((below % width) as i32, (below / width) as i32 - BUFFER_ROWS);
+                    below < self.matrix.len()
+                        && (self.matrix[below] == Cell::Empty || group.contains(&below))
+                        && !self.is_under_piece(x, y)
+                });
+                if can_fall {
+                    let cells: Vec<Cell> = group.iter().map(|idx| self.matrix[*idx]).collect();
+                    group.iter().for_each(|idx| self.matrix[*idx] = Cell::Empty);
+                    group
+                        .iter()
+                        .zip(cells)
+                        .for_each(|(idx, cell)| self.matrix[idx + width] = cell);
+                    has_moved = true;
+                }
+            }
+            if !has_moved {
+                return has_fallen;
+            }
+            has_fallen = true;
+        }
+    }
+    /// solid blocks joined up, down, left or right as lists of matrix indices, lowest groups first
+    fn connected_groups(&self) -> Vec<Vec<usize>> {
+        let width = self.width() as usize;
+        let mut is_grouped = vec![false; self.matrix.len()];
+        let mut groups = Vec::new();
+        for start in (0..self.matrix.len()).rev() {
+            if is_grouped[start] || !self.matrix[start].is_solid() {
+                continue;
+            }
+            is_grouped[start] = true;
+            let mut group = vec![start];
+            let mut unvisited = vec![start];
+            while let Some(idx) = unvisited.pop() {
+                let neighbours = [
+                    idx.checked_sub(width),
+                    Some(idx + width).filter(|below| *below < self.matrix.len()),
+                    Some(idx).filter(|idx| idx % width > 0).map(|idx| idx - 1),
+                    Some(idx + 1).filter(|right| right % width > 0),
+                ];
+                for neighbour in neighbours.into_iter().flatten() {
+                    if !is_grouped[neighbour] && self.matrix[neighbour].is_solid() {
+                        is_grouped[neighbour] = true;
+                        group.push(neighbour);
+                        unvisited.push(neighbour);
+                    }
+                }
+            }
+            groups.push(group);
+        }
+        groups
+    }
+}
+/// a single value in a line of [Engine::save_state], floats are saved as their bits so they come
+/// back exactly
+trait StateWord: Sized {
+    fn to_word(&self) -> String;
+    fn from_word(word: &str) -> Option<Self>;
+}
+macro_rules! display_state_word {
+    ($($value:ty),*) => {$(
+        impl StateWord for $value {
+            fn to_word(&self) -> String {
+                self.to_string()
+            }
+            fn from_word(word: &str) -> Option<Self> {
+                word.parse().ok()
+            }
+        }
+    )*};
+}
+display_state_word!(bool, i32, u8, u32, usize);
+impl StateWord for f32 {
+    fn to_word(&self) -> String {
+        format!("{:08x}", self.to_bits())
+    }
+    fn from_word(word: &str) -> Option<Self> {
+        u32::from_str_radix(word, 16).ok().map(f32::from_bits)
+    }
+}
+impl StateWord for u64 {
+    fn to_word(&self) -> String {
+        format!("{self:016x}")
+    }
+    fn from_word(word: &str) -> Option<Self> {
+        u64::from_str_radix(word, 16).ok()
+    }
+}
+impl StateWord for PieceType {
+    fn to_word(&self) -> String {
+        self.0.to_string()
+    }
+    fn from_word(word: &str) -> Option<Self> {
+        word.parse().ok().map(PieceType)
+    }
+}
+impl StateWord for Ability {
+    fn to_word(&self) -> String {
+        format!("{self:?}")
+    }
+    fn from_word(word: &str) -> Option<Self> {
+        Ability::ALL
+            .into_iter()
+            .find(|ability| format!("{ability:?}") == word)
+    }
+}
+impl StateWord for Cell {
+    /// `.` empty, `#` a block, `*` flashing, `f` and `b` followed by the locks left for burning
+    /// blocks and bombs
+    fn to_word(&self) -> String {
+        match self {
+            Cell::Empty => ".".to_string(),
+            Cell::Active => "#".to_string(),
+            Cell::Flashing => "*".to_string(),
+            Cell::Burning(locks) => {
+                format!("f{locks}")
+            }
+            Cell::Bomb(locks) => {
+                format!("b{locks}")
+            }
+        }
+    }
+    fn from_word(word: &str) -> Option<Self> {
+        match word {
+            "." => Some(Cell::Empty),
+            "#" => Some(Cell::Active),
+            "*" => Some(Cell::Flashing),
+            _ => {
+                if let Some(locks) = word.strip_prefix('f') {
+                    locks.parse().ok().map(Cell::Burning)
+                } else {
+                    word.strip_prefix('b')?.parse().ok().map(Cell::Bomb)
+                }
+            }
+        }
+    }
+}
+/// a field saved on its own line of [Engine::save_state]
+trait StateField {
+    fn save(&self) -> String;
+    fn load(&mut self, words: &[&str]) -> Option<()>;
+}
+impl<T: StateWord> StateField for T {
+    fn save(&self) -> String {
+        self.to_word()
+    }
+    fn load(&mut self, words: &[&str]) -> Option<()> {
+        let [word] = words else {
+            return None;
+        };
+        *self = T::from_word(word)?;
+        Some(())
+    }
+}
+impl<T: StateWord> StateField for Option<T> {
+    fn save(&self) -> String {
+        self.as_ref().map_or("none".to_string(), T::to_word)
+    }
+    fn load(&mut self, words: &[&str]) -> Option<()> {
+        *self = match words {
+            ["none"] => None,
+            [word] => Some(T::from_word(word)?),
+            _ => return None,
+        };
+        Some(())
+    }
+}
+impl<T: StateWord> StateField for Vec<T> {
+    fn save(&self) -> String {
+        self.iter().map(T::to_word).collect::<Vec<_>>().join(" ")
+    }
+    fn load(&mut self, words: &[&str]) -> Option<()> {
+        *self = words
+            .iter()
+            .map(|word| T::from_word(word))
+            .collect::<Option<_>>()?;
+        Some(())
+    }
+}
+impl<T: StateWord> StateField for VecDeque<T> {
+    fn save(&self) -> String {
+        self.iter().map(T::to_word).collect::<Vec<_>>().join(" ")
+    }
+    fn load(&mut self, words: &[&str]) -> Option<()> {
+        *self = words
+            .iter()
+            .map(|word| T::from_word(word))
+            .collect::<Option<_>>()?;
+        Some(())
+    }
+}
+impl<T: StateWord, const N: usize> StateField for [T; N] {
+    fn save(&self) -> String {
+        self.iter().map(T::to_word).collect::<Vec<_>>().join(" ")
+    }
+    fn load(&mut self, words: &[&str]) -> Option<()> {
+        let values: Vec<T> = words
+            .iter()
+            .map(|word| T::from_word(word))
+            .collect::<Option<_>>()?;
+        *self = values.try_into().ok()?;
+        Some(())
+    }
+}
+/// a saved engine state that couldn't be read, `line` counting from the first line of the state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateError {
+    pub line: usize,
+    pub reason: String,
+}
+impl Engine {
+    /// everything about the engine that changes during a stage, by name, in the order it's saved
+    fn state_fields(&mut self) -> [(&'static str, &mut dyn StateField); 33] {
+        [
+            ("next", &mut self.next_piece_types),
+            ("hold", &mut self.held_piece_type),
+            ("can-hold", &mut self.can_hold),
+            ("generator-rng", &mut self.generator.rng.state),
+            ("generator-bag", &mut self.generator.bag),
+            ("generator-last", &mut self.generator.last_piece_type),
+            ("drop-down-speed", &mut self.drop_down_speed),
+            ("turn-time", &mut self.turn_time_elapsed),
+            ("lock-time", &mut self.lock_time_elapsed),
+            ("lock-resets", &mut self.lock_resets),
+            ("lowest-y", &mut self.lowest_y),
+            ("last-kick", &mut self.last_kick_idx),
+            ("grid-time", &mut self.grid_time_elapsed),
+            ("topped-out", &mut self.is_topped_out),
+            ("rows-cleared", &mut self.rows_cleared),
+            ("combo", &mut self.combo),
+            ("back-to-back", &mut self.is_back_to_back),
+            ("last-clear-difficult", &mut self.was_last_clear_difficult),
+            ("time", &mut self.time_elapsed),
+            ("shift-direction", &mut self.shift_direction),
+            ("shift-time", &mut self.shift_time_elapsed),
+            ("soft-drop-time", &mut self.soft_drop_time_elapsed),
+            ("pending-garbage", &mut self.pending_garbage),
+            ("attacks-received", &mut self.attacks_received),
+            ("hazards-triggered", &mut self.hazards_triggered),
+            ("haste-time", &mut self.haste_time_remaining),
+            ("time-stop-time", &mut self.time_stop_remaining),
+            ("hazard-rng", &mut self.hazard_rng.state),
+            ("selected-ability", &mut self.selected_ability),
+            ("ability-energy", &mut self.ability_energy),
+            ("gravity-stop-time", &mut self.gravity_stop_remaining),
+            ("leaf-shielded", &mut self.is_leaf_shielded),
+            ("chain", &mut self.chain),
+        ]
+    }
+    /// the engine's state as text, to suspend a stage and pick it back up with
+    /// [Engine::load_state]. the settings and seed aren't included. each line is a name and its
+    /// values: a `row` line with its number for every row of the matrix, `piece` with the active
+    /// piece's type, position and rotation, then the rest of the fields
+    pub fn save_state(&self) -> String {
+        let width = self.width() as usize;
+        let mut lines: Vec<String> = self
+            .matrix
+            .chunks(width)
+            .enumerate()
+            .map(|(row_idx, row)| format!("row {row_idx} {}", row.to_vec().save()))
+            .collect();
+        lines.push(match self.piece {
+            Some(piece) => format!(
+                "piece {} {} {} {}",
+                piece.piece_type.0, piece.x, piece.y, piece.rotation
+            ),
+            None => "piece none".to_string(),
+        });
+        for (name, field) in self.clone().state_fields() {
+            lines.push(format!("{name} {}", field.save()));
+        }
+        lines.join("\n")
+    }
+    /// rebuild an engine from [Engine::save_state], with the settings and seed it was created with.
+    /// every field has to be there, so a state from an older version gets turned away instead of
+    /// half loaded
+    pub fn load_state(settings: Settings, seed: u64, state: &str) -> Result<Self, StateError> {
+        let mut engine = Engine::new(settings, seed);
+        let width = engine.width() as usize;
+        let row_count = engine.matrix.len() / width;
+        let mut rows_loaded = vec![false; row_count];
+        let mut is_piece_loaded = false;
+        let mut fields_loaded = vec![false; engine.state_fields().len()];
+        let error = |line: usize, reason: &str| StateError {
+            line,
+            reason: reason.to_string(),
+        };
+        for (line_idx, line) in state
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+        {
+            let line_no = line_idx + 1;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (name, values) = (words[0], &words[1..]);
+            match name {
+                "row" => {
+                    let row_idx = values
+                        .first()
+                        .and_then(|row_idx| row_idx.parse::<usize>().ok())
+                        .filter(|row_idx| *row_idx < row_count)
+                        .ok_or_else(|| error(line_no, "bad row number"))?;
+                    let mut row = Vec::new();
+                    if row.load(&values[1..]).is_none() || row.len() != width {
+                        return Err(error(
+                            line_no,
+                            &format!("row {row_idx} needs {width} cells"),
+                        ));
+                    }
+                    engine.matrix[row_idx * width..][..width].copy_from_slice(&row);
+                    rows_loaded[row_idx] = true;
+                }
+                "piece" => {
+                    engine.piece = match values {
+                        ["none"] => None,
+                        [piece_type, x, y, rotation] => {
+                            let piece_type = PieceType::from_word(piece_type)
+                                .filter(|piece_type| {
+                                    piece_type.0 < engine.settings.piece_set.pieces.len()
+                                })
+                                .ok_or_else(|| error(line_no, "unknown piece"))?;
+                            Some(Piece {
+                                x: x.parse().map_err(|_| error(line_no, "bad piece column"))?,
+                                y: y.parse().map_err(|_| error(line_no, "bad piece row"))?,
+                                rotation: rotation
+                                    .parse()
+                                    .ok()
+                                    .filter(|rotation| *rotation < 4)
+                                    .ok_or_else(|| error(line_no, "bad piece rotation"))?,
+                                ..engine.settings.piece_set.spawn(piece_type, width as i32)
+                            })
+                        }
+                        _ => return Err(error(line_no, "bad piece")),
+                    };
+                    is_piece_loaded = true;
+                }
+                _ => {
+                    let field_idx = engine
+                        .state_fields()
+                        .iter()
+                        .position(|(field_name, _)| *field_name == name)
+                        .ok_or_else(|| error(line_no, &format!("unknown field `{name}`")))?;
+                    engine.state_fields()[field_idx]
+                        .1
+                        .load(values)
+                        .ok_or_else(|| error(line_no, &format!("bad {name}")))?;
+                    fields_loaded[field_idx] = true;
+                }
+            }
+        }
+        let line_count = state.lines().count();
+        if let Some(row_idx) = rows_loaded.iter().position(|is_loaded| !is_loaded) {
+            return Err(error(line_count, &format!("missing row {row_idx}")));
+        }
+        if !is_piece_loaded {
+            return Err(error(line_count, "missing the piece"));
+        }
+        if let Some(field_idx) = fields_loaded.iter().position(|is_loaded| !is_loaded) {
+            let (name, _) = engine.state_fields()[field_idx];
+            return Err(error(line_count, &format!("missing {name}")));
+        }
+        // pieces from a different piece set would deal out of range
+        let piece_count = engine.settings.piece_set.pieces.len();
+        if engine
+            .next_piece_types
+            .iter()
+            .chain(&engine.generator.bag)
+            .chain(&engine.held_piece_type)
+            .chain(&engine.generator.last_piece_type)
+            .any(|piece_type| piece_type.0 >= piece_count)
+        {
+            return Err(error(line_count, "pieces missing from the piece set"));
+        }
+        Ok(engine)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// step the engine through `seconds` of 60 Hz ticks, pressing `inputs` on the first one
+    fn run(engine: &mut Engine, inputs: &[Input], seconds: f32) -> Vec<Event> {
+        let mut events = Vec::new();
+        for tick in 0..(seconds * 60.).ceil() as usize {
+            let inputs: &[Input] = if tick == 0 { inputs } else { &[] };
+            events.extend(engine.update(inputs, 1. / 60.));
+        }
+        events
+    }
+    /// fill the cells of row `y` apart from the columns in `holes`
+    fn fill_row(engine: &mut Engine, y: i32, holes: std::ops::Range<i32>) {
+        for x in (0..engine.width()).filter(|x| !holes.contains(x)) {
+            let idx = engine.matrix_idx(x, y);
+            engine.matrix[idx] = Cell::Active;
+        }
+    }
+    fn place(piece_type: PieceType, x: i32, y: i32, rotation: usize) -> Piece {
+        Piece {
+            x,
+            y,
+            rotation,
+            ..PieceSet::default().spawn(piece_type, GRID_WIDTH)
+        }
+    }
+    #[test]
+    fn pieces_spawn_fall_and_lock() {
+        let mut engine = Engine::new(Settings::default(), 42);
+        let events = run(&mut engine, &[], 1.);
+        assert!(matches!(events[0], Event::PieceSpawned(_)));
+        let events = run(&mut engine, &[], 30.);
+        assert!(events.contains(&Event::PieceLocked));
+        assert!(
+            engine
+                .matrix
+                .iter()
+                .filter(|cell| **cell == Cell::Active)
+                .count()
+                >= 4
+        );
+    }
+    #[test]
+    fn full_rows_clear_and_the_stack_moves_down() {
+        let mut engine = Engine::new(Settings::default(), 1);
+        fill_row(&mut engine, GRID_HEIGHT - 1, 4..6);
+        fill_row(&mut engine, GRID_HEIGHT - 2, 4..6);
+        let marker = engine.matrix_idx(0, 0);
+        engine.matrix[marker] = Cell::Active;
+        engine.piece = Some(place(PieceType::O, 4, 0, 0));
+        let mut events = engine.update(&[Input::HardDrop], 0.);
+        events.extend(run(&mut engine, &[], 1.));
+        assert!(events.contains(&Event::RowsFlagged(2)), "{events:?}");
+        assert!(!engine.has_flashing_rows());
+        assert_eq!(engine.rows_cleared, 2);
+        assert_eq!(engine.cell(0, 0), Cell::Empty);
+        assert_eq!(engine.cell(0, 2), Cell::Active);
+    }
+    #[test]
+    fn walls_block_moves() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        engine.piece = Some(place(PieceType::T, 0, 3, 0));
+        engine.update(&[Input::MoveLeft], 0.);
+        assert_eq!(engine.piece.unwrap().x, 0);
+        engine.update(&[Input::MoveRight], 0.);
+        assert_eq!(engine.piece.unwrap().x, 1);
+    }
+    #[test]
+    fn rotations_kick_off_the_walls() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        // pointing right against the left wall, pointing down needs the column to its left
+        engine.piece = Some(place(PieceType::T, -1, 5, 1));
+        assert!(engine.fits(&engine.piece.unwrap()));
+        engine.update(&[Input::RotateClockwise], 0.);
+        let piece = engine.piece.unwrap();
+        assert_eq!((piece.rotation, piece.x), (2, 0));
+        // upright I against the right wall, lying flat needs the columns to its right
+        engine.piece = Some(place(PieceType::I, 7, 5, 1));
+        assert!(engine.fits(&engine.piece.unwrap()));
+        engine.update(&[Input::RotateCounterClockwise], 0.);
+        let piece = engine.piece.unwrap();
+        assert_eq!(piece.rotation, 0);
+        assert!(piece.blocks().iter().all(|(x, _)| *x < GRID_WIDTH));
+    }
+    #[test]
+    fn rotations_with_no_room_are_refused() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        engine.matrix.fill(Cell::Active);
+        let piece = place(PieceType::T, 3, 5, 0);
+        for (x, y) in piece.blocks() {
+            let idx = engine.matrix_idx(x, y);
+            engine.matrix[idx] = Cell::Empty;
+        }
+        engine.piece = Some(piece);
+        engine.update(&[Input::RotateClockwise], 0.);
+        assert_eq!(engine.piece, Some(piece));
+        assert_eq!(wall_kicks(Kicks::None, 0, true), &[(0, 0)]);
+    }
+    #[test]
+    fn seven_bag_deals_every_piece_once_per_bag() {
+        let mut generator = PieceGenerator::new(Randomizer::SevenBag, 7, 99);
+        for _ in 0..10 {
+            let mut bag: Vec<_> = (0..7).map(|_| generator.next_piece_type()).collect();
+            bag.sort_by_key(|piece_type| piece_type.0);
+            bag.dedup();
+            assert_eq!(bag.len(), 7);
+        }
+    }
+    #[test]
+    fn seeds_repeat_the_piece_order() {
+        for randomizer in [
+            Randomizer::SevenBag,
+            Randomizer::Random,
+            Randomizer::NesReroll,
+        ] {
+            let deal = |seed| {
+                let mut generator = PieceGenerator::new(randomizer, 7, seed);
+                (0..50)
+                    .map(|_| generator.next_piece_type())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(deal(5), deal(5));
+            assert_ne!(deal(5), deal(6));
+        }
+    }
+    #[test]
+    fn moving_resets_the_lock_delay_a_limited_number_of_times() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        engine.piece = Some(place(PieceType::O, 4, GRID_HEIGHT - 2, 0));
+        engine.lowest_y = GRID_HEIGHT - 2;
+        // rest for most of the lock delay, then slide to start it over
+        let mut slides = 0;
+        while !engine
+            .update(&[], LOCK_DELAY * 0.9)
+            .contains(&Event::PieceLocked)
+        {
+            let direction = if slides % 2 == 0 {
+                Input::MoveLeft
+            } else {
+                Input::MoveRight
+            };
+            engine.update(&[direction], 0.);
+            slides += 1;
+            assert!(
+                slides <= MAX_LOCK_RESETS + 2,
+                "the lock delay kept resetting"
+            );
+        }
+        assert!(slides >= MAX_LOCK_RESETS, "{slides}");
+    }
+    #[test]
+    fn resting_pieces_lock_after_the_lock_delay() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        engine.piece = Some(place(PieceType::O, 4, GRID_HEIGHT - 2, 0));
+        engine.lowest_y = GRID_HEIGHT - 2;
+        assert!(
+            !engine
+                .update(&[], LOCK_DELAY * 0.9)
+                .contains(&Event::PieceLocked)
+        );
+        assert!(
+            engine
+                .update(&[], LOCK_DELAY * 0.2)
+                .contains(&Event::PieceLocked)
+        );
+    }
+    #[test]
+    fn t_spin_double_is_a_full_t_spin() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        let h = GRID_HEIGHT;
+        // a t-spin double slot: a hole in the bottom row under a gap in the row above, with an
+        // overhang
+        fill_row(&mut engine, h - 1, 4..5);
+        fill_row(&mut engine, h - 2, 3..6);
+        let overhang = engine.matrix_idx(3, h - 3);
+        engine.matrix[overhang] = Cell::Active;
+        engine.piece = Some(place(PieceType::T, 3, h - 3, 1));
+        engine.update(&[Input::RotateClockwise], 0.);
+        assert_eq!(engine.piece.unwrap().rotation, 2);
+        assert_eq!(engine.t_spin(), Some(TSpin::Full));
+        let events = engine.update(&[Input::HardDrop], 0.);
+        assert!(events.contains(&Event::TSpin(TSpin::Full)), "{events:?}");
+        assert!(events.contains(&Event::RowsFlagged(2)), "{events:?}");
+    }
+    #[test]
+    fn t_spins_with_one_front_corner_are_minis() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        let h = GRID_HEIGHT;
+        fill_row(&mut engine, h - 1, 4..5);
+        let front_corner = engine.matrix_idx(3, h - 3);
+        engine.matrix[front_corner] = Cell::Active;
+        engine.piece = Some(place(PieceType::T, 3, h - 3, 1));
+        engine.update(&[Input::RotateCounterClockwise], 0.);
+        assert_eq!(engine.piece.unwrap().rotation, 0);
+        assert_eq!(engine.t_spin(), Some(TSpin::Mini));
+        // sliding out and back in after the rotation means the piece wasn't spun in
+        engine.update(&[Input::MoveRight], 0.);
+        engine.update(&[Input::MoveLeft], 0.);
+        assert_eq!(engine.piece.unwrap().x, 3);
+        assert_eq!(engine.t_spin(), None);
+    }
+    #[test]
+    fn spawning_into_the_stack_blocks_out() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        fill_row(&mut engine, -1, 0..1);
+        let events = run(&mut engine, &[], 1.);
+        assert!(
+            events.contains(&Event::TopOut(TopOut::BlockOut)),
+            "{events:?}"
+        );
+        assert!(engine.is_topped_out);
+    }
+    #[test]
+    fn locking_above_the_grid_locks_out() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        for y in 0..GRID_HEIGHT {
+            fill_row(&mut engine, y, 0..1);
+        }
+        let events = run(&mut engine, &[], 3.);
+        assert!(
+            events.contains(&Event::TopOut(TopOut::LockOut)),
+            "{events:?}"
+        );
+        assert!(engine.is_topped_out);
+    }
+    #[test]
+    fn locking_partly_above_the_grid_locks_out() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        let piece = place(PieceType::O, 3, -2, 0);
+        let columns: Vec<i32> = piece.blocks().iter().map(|(x, _)| *x).collect();
+        // a stack up to row 1 under the piece, so it lands across the top row and the buffer
+        for y in 1..GRID_HEIGHT {
+            fill_row(&mut engine, y, 9..10);
+        }
+        engine.piece = Some(piece);
+        let events = engine.update(&[Input::HardDrop], 0.);
+        assert!(
+            events.contains(&Event::TopOut(TopOut::LockOut)),
+            "{events:?}"
+        );
+        // unless the row it lands in is completed, bringing the rest of it into the grid
+        let mut engine = Engine::new(Settings::default(), 3);
+        for y in 1..GRID_HEIGHT {
+            fill_row(&mut engine, y, 9..10);
+        }
+        let (first, last) = (columns.iter().min().unwrap(), columns.iter().max().unwrap());
+        fill_row(&mut engine, 0, *first..last + 1);
+        engine.piece = Some(piece);
+        let mut events = engine.update(&[Input::HardDrop], 0.);
+        events.extend(run(&mut engine, &[], 1.));
+        assert!(
+            !events.iter().any(|event| matches!(event, Event::TopOut(_))),
+            "{events:?}"
+        );
+        assert!(!engine.is_topped_out);
+    }
+    #[test]
+    fn the_game_only_ends_once() {
+        let mut engine = Engine::new(Settings::default(), 3);
+        for y in 0..GRID_HEIGHT {
+            fill_row(&mut engine, y, 9..10);
+        }
+        // a piece locking out in the buffer rows as garbage pushes it off the top
+        engine.queue_garbage(BUFFER_ROWS as u32);
+        engine.piece = Some(place(PieceType::O, 0, -2, 0));
+        let mut events = engine.update(&[Input::HardDrop], 0.);
+        events.extend(run(&mut engine, &[], 1.));
+        let top_outs: Vec<_> = events
+            .iter()
+            .filter(|event| matches!(event, Event::TopOut(_)))
+            .collect();
+        assert_eq!(top_outs, [&Event::TopOut(TopOut::GarbageOut)]);
+    }
+    #[test]
+    fn atomic_fire_only_burns_rows_with_blocks() {
+        let mut engine = Engine::new(Settings::default(), 5);
+        engine.selected_ability = Some(Ability::AtomicFire);
+        engine.ability_energy[Ability::AtomicFire.idx()] = WEAPON_ENERGY_MAX;
+        let mut events = Vec::new();
+        engine.use_ability(&mut events);
+        assert!(events.is_empty());
+        assert!(!engine.has_flashing_rows());
+        assert_eq!(
+            engine.ability_energy(Ability::AtomicFire),
+            WEAPON_ENERGY_MAX
+        );
+        fill_row(&mut engine, GRID_HEIGHT - 1, 3..4);
+        engine.use_ability(&mut events);
+        assert_eq!(events, [Event::AbilityUsed(Ability::AtomicFire)]);
+        assert_eq!(
+            engine.ability_energy(Ability::AtomicFire),
+            WEAPON_ENERGY_MAX - Ability::AtomicFire.energy_cost()
+        );
+        assert_eq!(engine.cell(3, GRID_HEIGHT - 1), Cell::Flashing);
+        assert_eq!(engine.cell(3, GRID_HEIGHT - 2), Cell::Empty);
+    }
+    #[test]
+    fn the_active_piece_moves_down_with_the_stack() {
+        let mut engine = Engine::new(Settings::default(), 5);
+        let h = GRID_HEIGHT;
+        fill_row(&mut engine, h - 1, 0..1);
+        // an overhang right above the active piece
+        let overhang = engine.matrix_idx(4, h - 5);
+        engine.matrix[overhang] = Cell::Active;
+        engine.piece = Some(place(PieceType::O, 4, h - 4, 0));
+        assert!(engine.flash_rows(h - 2..h));
+        engine.grid_time_elapsed = ROW_FLASH_TIME;
+        let mut events = Vec::new();
+        engine.clear_lines(&mut events);
+        assert_eq!(events, [Event::RowCleared(h - 1)]);
+        let piece = engine.piece.unwrap();
+        assert_eq!(piece.y, h - 3);
+        assert!(engine.fits(&piece));
+        assert_eq!(engine.cell(4, h - 4), Cell::Active);
+    }
+    #[test]
+    fn piece_sets_turn_pieces_around_their_center() {
+        let piece_set = PieceSet::parse("piece T\nt-spin\n.#.\n#@#\n").unwrap();
+        let standard = PieceSet::default();
+        for rotation in 0..4 {
+            let mut cells = piece_set.pieces[0].shape.cells(rotation).to_vec();
+            let mut standard_cells = standard.get(PieceType::T).shape.cells(rotation).to_vec();
+            cells.sort();
+            standard_cells.sort();
+            assert_eq!(cells, standard_cells, "rotation {rotation}");
+        }
+        assert!(piece_set.pieces[0].can_t_spin);
+    }
+    #[test]
+    fn piece_set_errors_point_at_the_line() {
+        assert_eq!(
+            PieceSet::parse("").unwrap_err().reason,
+            "no pieces in the set"
+        );
+        assert_eq!(PieceSet::parse("#@#").unwrap_err().line, 1);
+        assert_eq!(PieceSet::parse("piece X\nwobble\n").unwrap_err().line, 2);
+        assert!(PieceSet::parse("piece X\n### ###\n").is_err());
+        assert_eq!(
+            PieceSet::parse("piece A\n@#\npiece B\n##\n")
+                .unwrap_err()
+                .line,
+            4
+        );
+    }
+    #[test]
+    fn cascades_chain_into_more_clears() {
+        let settings = Settings {
+            clear_gravity: ClearGravity::Cascade,
+            ..Settings::default()
+        };
+        let mut engine = Engine::new(settings, 3);
+        let h = GRID_HEIGHT;
+        // the bottom row is missing its first block, which sits on the full row above and falls
+        // into the gap once that row clears
+        fill_row(&mut engine, h - 1, 0..1);
+        fill_row(&mut engine, h - 2, 0..0);
+        let block = engine.matrix_idx(0, h - 3);
+        engine.matrix[block] = Cell::Active;
+        engine.piece = None;
+        let events = run(&mut engine, &[], 1.);
+        let chain = events.iter().position(|event| *event == Event::Chain(1));
+        assert!(chain.is_some(), "{events:?}");
+        assert_eq!(events[chain.unwrap() + 1], Event::RowsFlagged(1));
+        assert_eq!(engine.rows_cleared, 2);
+    }
+    /// one 60 Hz tick of busy play, with inputs picked by `rng` plus regular garbage, hazards and
+    /// abilities
+    fn busy_tick(engine: &mut Engine, tick: u32, rng: &mut XorShift64) {
+        let roll = u64::from(rng.next_u32()) << 32 | u64::from(rng.next_u32());
+        let inputs: Vec<Input> = [
+            Input::MoveLeft,
+            Input::MoveRight,
+            Input::SoftDrop,
+            Input::FirmDrop,
+            Input::HardDrop,
+            Input::RotateClockwise,
+            Input::RotateCounterClockwise,
+            Input::Hold,
+        ]
+        .into_iter()
+        .enumerate()
+        .filter(|(bit, _)| (roll >> (bit * 6)) & 63 == 0)
+        .map(|(_, input)| input)
+        .collect();
+        let mut events = Vec::new();
+        if tick.is_multiple_of(600) {
+            engine.queue_garbage(2);
+        }
+        if tick.is_multiple_of(450) {
+            let hazard = [Hazard::Ignite, Hazard::Bomb, Hazard::Haste, Hazard::Gust]
+                [(tick / 450 % 4) as usize];
+            engine.trigger_hazard(hazard, &mut events);
+        }
+        if tick.is_multiple_of(300) {
+            engine.selected_ability = Some(Ability::ALL[(tick / 300 % 8) as usize]);
+            engine.use_ability(&mut events);
+        }
+        engine.update(&inputs, 1. / 60.);
+    }
+    #[test]
+    fn saved_state_plays_on_the_same() {
+        for (seed, clear_gravity, randomizer) in [
+            (7, ClearGravity::Naive, Randomizer::SevenBag),
+            (99, ClearGravity::Cascade, Randomizer::NesReroll),
+            (3, ClearGravity::Naive, Randomizer::Random),
+        ] {
+            let settings = Settings {
+                clear_gravity,
+                randomizer,
+                ..Settings::default()
+            };
+            let mut engine = Engine::new(settings.clone(), seed);
+            let mut rng = XorShift64::new(seed);
+            for tick in 1..400 {
+                busy_tick(&mut engine, tick, &mut rng);
+            }
+            assert!(!engine.is_topped_out);
+            let state = engine.save_state();
+            let mut restored = Engine::load_state(settings, seed, &state).unwrap();
+            assert_eq!(restored.save_state(), state);
+            let mut restored_rng = rng.clone();
+            for tick in 400..3000 {
+                busy_tick(&mut engine, tick, &mut rng);
+                busy_tick(&mut restored, tick, &mut restored_rng);
+            }
+            assert_eq!(restored.save_state(), engine.save_state());
+            assert_eq!(restored.matrix, engine.matrix);
+        }
+    }
+    #[test]
+    fn bad_state_is_turned_away() {
+        let state = Engine::new(Settings::default(), 5).save_state();
+        let reason = |state: &str| {
+            Engine::load_state(Settings::default(), 5, state)
+                .unwrap_err()
+                .reason
+        };
+        assert_eq!(
+            reason(&state.replace("row 3 ", "row 30 ")),
+            "bad row number"
+        );
+        assert_eq!(
+            reason(&state.replacen("combo 0", "combo x", 1)),
+            "bad combo"
+        );
+        assert_eq!(reason(&state.replacen("chain 0", "", 1)), "missing chain");
+        assert_eq!(
+            reason(&state.replacen("chain 0", "chain 0\nbogus 1", 1)),
+            "unknown field `bogus`"
+        );
+        assert!(reason(&state.replacen("row 4 .", "row 4", 1)).contains("needs 10 cells"));
+        let three = PieceSet {
+            pieces: PieceSet::default().pieces[..3].to_vec(),
+        };
+        let settings = Settings {
+            piece_set: three,
+            ..Settings::default()
+        };
+        assert_eq!(
+            Engine::load_state(settings, 5, &state).unwrap_err().reason,
+            "pieces missing from the piece set"
+        );
+    }
+}
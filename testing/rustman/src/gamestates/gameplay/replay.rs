@@ -0,0 +1,542 @@
+//! recording stages and playing them back, to reproduce a player's bug report or verify a best
+//! time. the puzzle rules are deterministic, so the seed, the robot, the player's settings and the
+//! input for every tick are enough to play a stage out exactly the same way again
+use super::storage::{self, Storage};
+use super::{ROBOT_MASTERS, puzzle};
+use crate::npc::NPCName;
+/// first line of every replay file, followed by the version of the format
+pub const REPLAY_HEADER: &str = "RUSTMAN REPLAY";
+/// bump whenever the format changes so older replays get turned away instead of played out wrong
+pub const REPLAY_VERSION: u32 = 2;
+/// where the last stage played is saved
+pub const REPLAY_FILE: &str = "replay.dat";
+/// longest stage a replay holds, an hour of ticks. the tick count comes from the file, so a
+/// damaged count can't have a stage of any length set aside before its ticks are read
+pub const MAX_REPLAY_TICKS: usize = 60 * 60 * super::TICKS_PER_SECOND as usize;
+/// something the player did during a tick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Puzzle(puzzle::Input),
+    /// `None` switches back to the P Shooter
+    SwitchWeapon(Option<NPCName>),
+    UseAbility,
+}
+impl Action {
+    /// whether the action comes from a key being held down rather than pressed, so it carries
+    /// on through every tick of a frame instead of only the first
+    pub fn is_held(&self) -> bool {
+        matches!(
+            self,
+            Action::Puzzle(
+                puzzle::Input::MoveLeft
+                    | puzzle::Input::MoveRight
+                    | puzzle::Input::SoftDrop
+                    | puzzle::Input::FirmDrop
+            )
+        )
+    }
+}
+/// one fixed length tick of `gameplay()` and what the player did during it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tick {
+    pub actions: Vec<Action>,
+}
+impl Tick {
+    pub fn puzzle_inputs(&self) -> Vec<puzzle::Input> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Puzzle(input) => Some(*input),
+                _ => None,
+            })
+            .collect()
+    }
+    /// the weapon asked for this tick, `Some(None)` being the P Shooter
+    pub fn switched_weapon(&self) -> Option<Option<NPCName>> {
+        self.actions.iter().find_map(|action| match action {
+            Action::SwitchWeapon(weapon) => Some(*weapon),
+            _ => None,
+        })
+    }
+}
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub robot: NPCName,
+    /// the player's settings, the piece set comes from the robot's stage and isn't saved
+    pub settings: puzzle::Settings,
+    pub ticks: Vec<Tick>,
+}
+/// a replay file that couldn't be read, with the line it went wrong on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayError {
+    pub line: usize,
+    pub reason: String,
+}
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+const INPUT_NAMES: [(puzzle::Input, &str); 8] = [
+    (puzzle::Input::MoveLeft, "left"),
+    (puzzle::Input::MoveRight, "right"),
+    (puzzle::Input::SoftDrop, "soft-drop"),
+    (puzzle::Input::FirmDrop, "firm-drop"),
+    (puzzle::Input::HardDrop, "hard-drop"),
+    (puzzle::Input::RotateClockwise, "rotate-cw"),
+    (puzzle::Input::RotateCounterClockwise, "rotate-ccw"),
+    (puzzle::Input::Hold, "hold"),
+];
+/// a robot by its name in a replay or snapshot file
+pub(super) fn robot_named(name: &str) -> Option<NPCName> {
+    ROBOT_MASTERS
+        .into_iter()
+        .chain([NPCName::DrWily, NPCName::RustMan])
+        .find(|robot| format!("{robot:?}") == name)
+}
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Action::Puzzle(input) => {
+                let (_, name) = INPUT_NAMES
+                    .iter()
+                    .find(|(named, _)| named == input)
+                    .unwrap();
+                write!(f, "{name}")
+            }
+            Action::SwitchWeapon(Some(weapon)) => {
+                write!(f, "weapon-{weapon:?}")
+            }
+            Action::SwitchWeapon(None) => {
+                write!(f, "weapon-none")
+            }
+            Action::UseAbility => {
+                write!(f, "ability")
+            }
+        }
+    }
+}
+impl std::str::FromStr for Action {
+    type Err = String;
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        if let Some((input, _)) = INPUT_NAMES.iter().find(|(_, name)| *name == word) {
+            return Ok(Action::Puzzle(*input));
+        }
+        match word {
+            "ability" => Ok(Action::UseAbility),
+            "weapon-none" => Ok(Action::SwitchWeapon(None)),
+            _ => word
+                .strip_prefix("weapon-")
+                .and_then(robot_named)
+                .map(|weapon| Action::SwitchWeapon(Some(weapon)))
+                .ok_or_else(|| format!("unknown action `{word}`")),
+        }
+    }
+}
+impl std::fmt::Display for Replay {
+    /// the replay file: the header and version, the seed, robot and settings, how many ticks
+    /// the stage ran for, then a line with the number and actions of every tick the player did
+    /// something in
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{REPLAY_HEADER} {REPLAY_VERSION}")?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "robot {:?}", self.robot)?;
+        writeln!(f, "settings {}", Self::format_settings(&self.settings))?;
+        writeln!(f, "ticks {}", self.ticks.len())?;
+        for (tick_idx, tick) in self
+            .ticks
+            .iter()
+            .enumerate()
+            .filter(|(_, tick)| !tick.actions.is_empty())
+        {
+            write!(f, "tick {tick_idx}")?;
+            for action in &tick.actions {
+                write!(f, " {action}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+impl Replay {
+    pub fn new(seed: u64, robot: NPCName, settings: puzzle::Settings) -> Self {
+        Self {
+            seed,
+            robot,
+            settings,
+            ticks: Vec::new(),
+        }
+    }
+    /// read a replay file written by [Replay]'s `Display`
+    pub fn parse(text: &str) -> Result<Self, ReplayError> {
+        let error = |line: usize, reason: &str| ReplayError {
+            line,
+            reason: reason.to_string(),
+        };
+        match text
+            .lines()
+            .next()
+            .and_then(|header| header.strip_prefix(REPLAY_HEADER))
+        {
+            Some(version) if version.trim().parse() == Ok(REPLAY_VERSION) => {}
+            Some(_) => {
+                return Err(error(
+                    1,
+                    &format!("only version {REPLAY_VERSION} replays can be played"),
+                ));
+            }
+            None => {
+                return Err(error(1, "not a replay file"));
+            }
+        }
+        let lines: Vec<(usize, Vec<&str>)> = text
+            .lines()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line_idx, line)| (line_idx + 1, line.split_whitespace().collect()))
+            .collect();
+        // the values on the `idx`th line after the header, which has to start with `keyword`
+        let line = |idx: usize, keyword: &str| match lines.get(idx) {
+            Some((line_no, words)) if words[0] == keyword => Ok((*line_no, &words[1..])),
+            Some((line_no, _)) => Err(error(*line_no, &format!("expected the {keyword} line"))),
+            None => Err(error(
+                text.lines().count(),
+                &format!("missing the {keyword} line"),
+            )),
+        };
+        let (line_no, values) = line(0, "seed")?;
+        let seed = values
+            .first()
+            .and_then(|seed| seed.parse().ok())
+            .ok_or_else(|| error(line_no, "bad seed"))?;
+        let (line_no, values) = line(1, "robot")?;
+        let robot = values
+            .first()
+            .and_then(|name| robot_named(name))
+            .ok_or_else(|| error(line_no, "unknown robot"))?;
+        let (line_no, values) = line(2, "settings")?;
+        let settings =
+            Self::parse_settings(values).ok_or_else(|| error(line_no, "bad settings"))?;
+        let (line_no, values) = line(3, "ticks")?;
+        let tick_count = values
+            .first()
+            .and_then(|count| count.parse().ok())
+            .filter(|count| *count <= MAX_REPLAY_TICKS)
+            .ok_or_else(|| error(line_no, "bad tick count"))?;
+        // the ticks the player did nothing in aren't written, they're filled in as later ticks
+        // arrive and up to the count at the end
+        let mut ticks: Vec<Tick> = Vec::new();
+        let mut next_tick_idx = 0;
+        for idx in 4..lines.len() {
+            let (line_no, values) = line(idx, "tick")?;
+            // ticks come in order and within the count, so a file that was cut short or pasted
+            // together gets caught
+            let tick_idx = values
+                .first()
+                .and_then(|tick_idx| tick_idx.parse().ok())
+                .filter(|tick_idx| (next_tick_idx..tick_count).contains(tick_idx))
+                .ok_or_else(|| {
+                    error(
+                        line_no,
+                        &format!("expected a tick from {next_tick_idx} before {tick_count}"),
+                    )
+                })?;
+            let actions = values[1..]
+                .iter()
+                .map(|word| word.parse())
+                .collect::<Result<_, String>>()
+                .map_err(|reason| error(line_no, &reason))?;
+            ticks.resize(tick_idx, Tick::default());
+            ticks.push(Tick { actions });
+            next_tick_idx = tick_idx + 1;
+        }
+        ticks.resize(tick_count, Tick::default());
+        Ok(Self {
+            seed,
+            robot,
+            settings,
+            ticks,
+        })
+    }
+    /// the values of a `settings` line, shared with snapshots
+    pub(super) fn format_settings(settings: &puzzle::Settings) -> String {
+        format!(
+            "{:?} {} {} {} {} {} {} {} {:?}",
+            settings.randomizer,
+            settings.preview_count,
+            settings.show_ghost,
+            settings.dimensions.width,
+            settings.dimensions.height,
+            settings.das_ms,
+            settings.arr_ms,
+            settings.soft_drop_ms,
+            settings.clear_gravity,
+        )
+    }
+    /// read the values written by [Replay::format_settings]
+    pub(super) fn parse_settings(values: &[&str]) -> Option<puzzle::Settings> {
+        let [
+            randomizer,
+            preview_count,
+            show_ghost,
+            width,
+            height,
+            das_ms,
+            arr_ms,
+            soft_drop_ms,
+            clear_gravity,
+        ] = values
+        else {
+            return None;
+        };
+        Some(puzzle::Settings {
+            randomizer: match *randomizer {
+                "SevenBag" => puzzle::Randomizer::SevenBag,
+                "Random" => puzzle::Randomizer::Random,
+                "NesReroll" => puzzle::Randomizer::NesReroll,
+                _ => return None,
+            },
+            preview_count: preview_count.parse().ok()?,
+            show_ghost: show_ghost.parse().ok()?,
+            dimensions: puzzle::Dimensions {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+            },
+            das_ms: das_ms.parse().ok()?,
+            arr_ms: arr_ms.parse().ok()?,
+            soft_drop_ms: soft_drop_ms.parse().ok()?,
+            clear_gravity: match *clear_gravity {
+                "Naive" => puzzle::ClearGravity::Naive,
+                "Cascade" => puzzle::ClearGravity::Cascade,
+                _ => return None,
+            },
+            piece_set: puzzle::PieceSet::default(),
+        })
+    }
+    /// the replay kept in `storage`, `None` when no stage has been recorded yet
+    pub fn load(storage: &impl Storage) -> Result<Option<Self>, String> {
+        storage
+            .read()?
+            .map(|text| Self::parse(&text).map_err(|err| err.to_string()))
+            .transpose()
+    }
+}
+/// where the build being run keeps the last stage played
+pub fn default_storage() -> impl Storage {
+    storage::storage(REPLAY_FILE)
+}
+/// what the replay system is doing during a stage
+#[derive(Clone, Debug, PartialEq)]
+pub enum Session {
+    /// the player is playing and every tick is recorded
+    Recording(Replay),
+    /// a replay is being watched, `tick_idx` being the next tick to play back
+    Playing { replay: Replay, tick_idx: usize },
+    /// the stage is over and the recording has been saved
+    Finished,
+}
+impl Session {
+    /// the next recorded tick when watching a replay, `None` when the player's own input should
+    /// be used. once a replay runs out the player takes over
+    pub fn next_played_tick(&mut self) -> Option<Tick> {
+        match self {
+            Session::Recording(_) | Session::Finished => None,
+            Session::Playing { replay, tick_idx } => {
+                let tick = replay.ticks.get(*tick_idx).cloned();
+                *tick_idx += 1;
+                tick
+            }
+        }
+    }
+    /// add a tick to the recording. a stage running past [MAX_REPLAY_TICKS] stops being recorded
+    /// and isn't saved, as the replay couldn't be read back
+    pub fn record(&mut self, tick: &Tick) {
+        match self {
+            Session::Recording(replay) if replay.ticks.len() >= MAX_REPLAY_TICKS => {
+                *self = Session::Finished;
+            }
+            Session::Recording(replay) => replay.ticks.push(tick.clone()),
+            Session::Playing { .. } | Session::Finished => {}
+        }
+    }
+    /// save the recording to `storage` and stop recording. a replay being watched carries on
+    pub fn save(&mut self, storage: &mut impl Storage) -> Result<(), String> {
+        match std::mem::replace(self, Session::Finished) {
+            Session::Recording(replay) => storage.write(&replay.to_string()),
+            session => {
+                *self = session;
+                Ok(())
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puzzle::{ClearGravity, Engine, Settings};
+    /// a stage of busy play: runs of held inputs with weapon switches and abilities thrown in, then
+    /// a stretch where the player does nothing before the stage ends
+    fn busy_stage() -> Replay {
+        let settings = Settings {
+            das_ms: 133,
+            clear_gravity: ClearGravity::Cascade,
+            ..Settings::default()
+        };
+        let mut replay = Replay::new(0xdead_beef_1234, NPCName::AirMan, settings);
+        let mut roll: u64 = 88_172_645_463_325_252;
+        for tick in 0..2000 {
+            roll ^= roll << 13;
+            roll ^= roll >> 7;
+            roll ^= roll << 17;
+            let mut actions: Vec<Action> = INPUT_NAMES
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| (roll >> (bit * 3)) & 7 == 0)
+                .map(|(_, (input, _))| Action::Puzzle(*input))
+                .collect();
+            if tick % 97 == 0 {
+                actions.push(Action::SwitchWeapon(Some(NPCName::CrashMan)));
+            }
+            if tick % 131 == 0 {
+                actions.push(Action::SwitchWeapon(None));
+                actions.push(Action::UseAbility);
+            }
+            if !roll.is_multiple_of(3) || tick >= 1900 {
+                actions.clear();
+            }
+            replay.ticks.push(Tick { actions });
+        }
+        replay
+    }
+    /// the puzzle state at the end of `replay`, played through the rules the way a watched stage is
+    fn play(replay: &Replay) -> String {
+        let mut engine = Engine::new(replay.settings.clone(), replay.seed);
+        for tick in &replay.ticks {
+            if tick.actions.contains(&Action::UseAbility) {
+                engine.use_ability(&mut Vec::new());
+            }
+            engine.update(&tick.puzzle_inputs(), 1. / 60.);
+        }
+        engine.save_state()
+    }
+    fn parse_error(text: &str) -> (usize, String) {
+        let err = Replay::parse(text).unwrap_err();
+        (err.line, err.reason)
+    }
+    #[test]
+    fn watched_stages_end_on_the_same_board() {
+        let replay = busy_stage();
+        let parsed = Replay::parse(&replay.to_string()).unwrap();
+        assert_eq!(parsed, replay);
+        assert_eq!(play(&parsed), play(&replay));
+    }
+    #[test]
+    fn idle_ticks_keep_their_place() {
+        // idle ticks aren't written out, the tick count and numbers put them back
+        let replay = busy_stage();
+        let text = replay.to_string();
+        assert!(!text.contains("tick 1999"));
+        assert_eq!(Replay::parse(&text).unwrap().ticks.len(), 2000);
+        let empty = Replay::new(1, NPCName::DrWily, Settings::default());
+        assert_eq!(Replay::parse(&empty.to_string()), Ok(empty));
+    }
+    #[test]
+    fn other_versions_and_files_are_turned_away() {
+        let text = busy_stage().to_string();
+        let older = text.replacen("RUSTMAN REPLAY 2", "RUSTMAN REPLAY 1", 1);
+        assert_eq!(
+            parse_error(&older),
+            (1, "only version 2 replays can be played".to_string())
+        );
+        assert_eq!(parse_error(""), (1, "not a replay file".to_string()));
+        assert_eq!(
+            parse_error("RUSTMAN SNAPSHOT 1\nseed 4\n"),
+            (1, "not a replay file".to_string())
+        );
+    }
+    #[test]
+    fn cut_off_replays_point_at_where_they_stop() {
+        let text = busy_stage().to_string();
+        let header: String = text
+            .lines()
+            .take(2)
+            .map(|line| format!("{line}\n"))
+            .collect();
+        assert_eq!(
+            parse_error(&header),
+            (2, "missing the robot line".to_string())
+        );
+        // a download that stopped part way through a tick's actions
+        let last_tick = text
+            .lines()
+            .rfind(|line| line.starts_with("tick "))
+            .unwrap();
+        let cut = &text[..text.find(last_tick).unwrap() + last_tick.len() - 2];
+        let (line, reason) = parse_error(cut);
+        assert_eq!(
+            line,
+            text.lines().position(|line| line == last_tick).unwrap() + 1
+        );
+        assert!(reason.starts_with("unknown action"), "{reason}");
+    }
+    #[test]
+    fn out_of_range_values_are_refused() {
+        let text = busy_stage().to_string();
+        let too_big = text.replacen("seed 244837814047284", "seed 18446744073709551616", 1);
+        assert_eq!(parse_error(&too_big), (2, "bad seed".to_string()));
+        let first_tick = text.lines().find(|line| line.starts_with("tick ")).unwrap();
+        let (_, actions) = first_tick["tick ".len()..].split_once(' ').unwrap();
+        let past_the_end = text.replacen(first_tick, &format!("tick 2000 {actions}"), 1);
+        assert_eq!(
+            parse_error(&past_the_end).1,
+            "expected a tick from 0 before 2000"
+        );
+        // ticks repeated or out of order would play the stage out differently
+        let repeated = text.replacen(first_tick, &format!("{first_tick}\n{first_tick}"), 1);
+        assert_eq!(parse_error(&repeated).0, 7);
+        let robot = text.replacen("robot AirMan", "robot MegaMan", 1);
+        assert_eq!(parse_error(&robot), (3, "unknown robot".to_string()));
+    }
+    #[test]
+    fn tick_counts_past_an_hour_are_refused() {
+        let text = busy_stage().to_string();
+        let ticks_line = text.lines().position(|line| line == "ticks 2000").unwrap() + 1;
+        for count in [MAX_REPLAY_TICKS + 1, usize::MAX] {
+            let huge = text.replacen("ticks 2000", &format!("ticks {count}"), 1);
+            assert_eq!(
+                parse_error(&huge),
+                (ticks_line, "bad tick count".to_string())
+            );
+        }
+        // the longest stage is read, idle to the end
+        let longest = text.replacen("ticks 2000", &format!("ticks {MAX_REPLAY_TICKS}"), 1);
+        let replay = Replay::parse(&longest).unwrap();
+        assert_eq!(replay.ticks.len(), MAX_REPLAY_TICKS);
+        assert_eq!(replay.ticks[..2000], busy_stage().ticks[..]);
+        // and a recording that would run past it is dropped instead of saved unreadable
+        let mut recording = Session::Recording(replay);
+        recording.record(&Tick::default());
+        assert_eq!(recording, Session::Finished);
+    }
+    #[test]
+    fn only_recordings_are_saved() {
+        let path = std::env::temp_dir().join(format!("rustman-replay-{}.dat", std::process::id()));
+        let mut storage = storage::FileStorage { path };
+        storage.remove().unwrap();
+        assert_eq!(Replay::load(&storage), Ok(None));
+        let mut watching = Session::Playing {
+            replay: busy_stage(),
+            tick_idx: 3,
+        };
+        watching.save(&mut storage).unwrap();
+        assert!(matches!(watching, Session::Playing { tick_idx: 3, .. }));
+        assert_eq!(Replay::load(&storage), Ok(None));
+        let mut playing = Session::Recording(busy_stage());
+        playing.save(&mut storage).unwrap();
+        assert_eq!(playing, Session::Finished);
+        assert_eq!(Replay::load(&storage), Ok(Some(busy_stage())));
+        storage.remove().unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+//! the best times table in `scores.dat`: for each run a line with the minutes, a line with the
+//! seconds and a line with the runner's name, fastest first. the table is read and written through
+//! a [Storage], so the same code works with a file on the desktop and with the browser's local
+//! storage in the web build
+use super::storage::{self, Storage};
+/// the table shipped with the game, read until the player's own table has been saved
+pub const SCORES_FILE: &str = "assets/scores.dat";
+/// the player's own table, kept in their data directory rather than over the shipped one
+pub const SAVED_SCORES_FILE: &str = "scores.dat";
+/// runs kept in the table
+pub const MAX_SCORES: usize = 10;
+/// longest name that fits in the table
+pub const MAX_NAME_LEN: usize = 12;
+/// lines in the file for each run
+const ENTRY_LINES: usize = 3;
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BestTime {
+    pub minutes: u32,
+    /// 0 to 59
+    pub seconds: u32,
+    pub name: String,
+}
+impl BestTime {
+    /// a run's time from the game timer, in whole seconds like the timer shows it. the name is
+    /// upper cased and cut down to [MAX_NAME_LEN] to fit the table
+    pub fn new(time_elapsed: f32, name: &str) -> Self {
+        let total_seconds = time_elapsed.max(0.) as u32;
+        Self {
+            minutes: total_seconds / 60,
+            seconds: total_seconds % 60,
+            name: name
+                .trim()
+                .to_uppercase()
+                .chars()
+                .take(MAX_NAME_LEN)
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+        }
+    }
+    pub fn total_seconds(&self) -> u32 {
+        self.minutes * 60 + self.seconds
+    }
+}
+/// runs in the table, fastest first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BestTimes {
+    pub entries: Vec<BestTime>,
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScoresError {
+    /// a line of the table that couldn't be read, counting from 1
+    Malformed { line: usize, reason: String },
+    /// the table couldn't be read from or written to its [Storage]
+    Storage(String),
+}
+impl std::fmt::Display for ScoresError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScoresError::Malformed { line, reason } => write!(f, "line {line}: {reason}"),
+            ScoresError::Storage(reason) => {
+                write!(f, "{reason}")
+            }
+        }
+    }
+}
+/// where the build being run keeps the table once a run has been put in it
+pub fn default_storage() -> impl Storage {
+    storage::storage(SAVED_SCORES_FILE)
+}
+impl std::fmt::Display for BestTimes {
+    /// the table as it's saved, with the two digit minutes and seconds and the CRLF line
+    /// endings of the shipped file
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for best_time in &self.entries {
+            write!(
+                f,
+                "{:02}\r\n{:02}\r\n{}\r\n",
+                best_time.minutes, best_time.seconds, best_time.name
+            )?;
+        }
+        Ok(())
+    }
+}
+impl BestTimes {
+    /// read a table written by [BestTimes]'s `Display`. the runs are sorted fastest first and
+    /// only the first [MAX_SCORES] are kept, in case the file was edited by hand
+    pub fn parse(text: &str) -> Result<Self, ScoresError> {
+        let error = |line: usize, reason: &str| ScoresError::Malformed {
+            line,
+            reason: reason.to_string(),
+        };
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .collect();
+        // a blank line at the very end is left by editors, anywhere else it's a missing value
+        let line_count = lines
+            .iter()
+            .rposition(|line| !line.trim().is_empty())
+            .map_or(0, |idx| idx + 1);
+        let mut entries = Vec::new();
+        for entry_idx in (0..line_count).step_by(ENTRY_LINES) {
+            let line = |offset: usize, value: &str| {
+                lines
+                    .get(entry_idx + offset)
+                    .filter(|_| entry_idx + offset < line_count)
+                    .map(|line| (entry_idx + offset + 1, line.trim()))
+                    .ok_or_else(|| {
+                        error(line_count, &format!("the last run is missing its {value}"))
+                    })
+            };
+            let (line_no, minutes) = line(0, "minutes")?;
+            let minutes = minutes.parse().map_err(|_| {
+                error(
+                    line_no,
+                    &format!("minutes should be a number, not `{minutes}`"),
+                )
+            })?;
+            let (line_no, seconds) = line(1, "seconds")?;
+            let seconds = seconds
+                .parse()
+                .ok()
+                .filter(|seconds| *seconds < 60)
+                .ok_or_else(|| {
+                    error(
+                        line_no,
+                        &format!("seconds should be 0 to 59, not `{seconds}`"),
+                    )
+                })?;
+            let (line_no, name) = line(2, "name")?;
+            if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+                return Err(error(
+                    line_no,
+                    &format!("names should be 1 to {MAX_NAME_LEN} characters"),
+                ));
+            }
+            entries.push(BestTime {
+                minutes,
+                seconds,
+                name: name.to_string(),
+            });
+        }
+        entries.sort_by_key(BestTime::total_seconds);
+        entries.truncate(MAX_SCORES);
+        Ok(Self { entries })
+    }
+    /// the table kept in `storage`, `None` when it hasn't been saved there yet
+    pub fn load(storage: &impl Storage) -> Result<Option<Self>, ScoresError> {
+        storage
+            .read()
+            .map_err(ScoresError::Storage)?
+            .map(|text| Self::parse(&text))
+            .transpose()
+    }
+    pub fn save(&self, storage: &mut impl Storage) -> Result<(), ScoresError> {
+        storage
+            .write(&self.to_string())
+            .map_err(ScoresError::Storage)
+    }
+    /// add a run to the table behind any runs with the same time, keeping only the fastest
+    /// [MAX_SCORES]. returns the run's place in the table, or `None` when it wasn't fast enough
+    /// to make it
+    pub fn insert(&mut self, best_time: BestTime) -> Option<usize> {
+        let place = self
+            .entries
+            .iter()
+            .position(|entry| entry.total_seconds() > best_time.total_seconds())
+            .unwrap_or(self.entries.len());
+        if place >= MAX_SCORES {
+            return None;
+        }
+        self.entries.insert(place, best_time);
+        self.entries.truncate(MAX_SCORES);
+        Some(place)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// the table shipped in [SCORES_FILE], with the windows line endings it's written with
+    const SHIPPED: &str = concat!(
+        "03\r\n59\r\nPROTO MAN\r\n",
+        "05\r\n59\r\nROLL\r\n",
+        "07\r\n59\r\nDR. LIGHT\r\n",
+        "09\r\n59\r\nGUTS MAN\r\n",
+        "11\r\n00\r\nYO YO YO\r\n",
+        "11\r\n59\r\nSHADOW MAN\r\n",
+        "13\r\n59\r\nSKULL MAN\r\n",
+        "15\r\n59\r\nGRAVITY MAN\r\n",
+        "17\r\n59\r\nKNIGHT MAN\r\n",
+        "19\r\n59\r\nSLASH MAN\r\n",
+    );
+    fn malformed(text: &str) -> String {
+        BestTimes::parse(text).unwrap_err().to_string()
+    }
+    #[test]
+    fn the_shipped_table_is_written_back_byte_for_byte() {
+        let table = BestTimes::parse(SHIPPED).unwrap();
+        assert_eq!(table.entries.len(), MAX_SCORES);
+        let proto_man = BestTime {
+            minutes: 3,
+            seconds: 59,
+            name: "PROTO MAN".to_string(),
+        };
+        assert_eq!(table.entries[0], proto_man);
+        assert_eq!(table.to_string(), SHIPPED);
+        // a table edited by hand with unix line endings, out of order and with a trailing blank
+        // line
+        let edited = BestTimes::parse("05\n00\nB\n03\n00\nA\n\n\n").unwrap();
+        assert_eq!(edited.entries[0].name, "A");
+        assert_eq!(BestTimes::parse(""), Ok(BestTimes::default()));
+    }
+    #[test]
+    fn ties_go_behind_and_the_slowest_run_drops_off() {
+        let mut table = BestTimes::parse(SHIPPED).unwrap();
+        // 11:00.9 shows as 11:00 on the game timer, tying YO YO YO
+        assert_eq!(
+            table.insert(BestTime::new(11. * 60. + 0.9, "mega man")),
+            Some(5)
+        );
+        assert_eq!(table.entries[5].name, "MEGA MAN");
+        assert_eq!(table.entries.len(), MAX_SCORES);
+        assert_eq!(table.entries[MAX_SCORES - 1].name, "KNIGHT MAN");
+        assert_eq!(
+            table.insert(BestTime::new(19. * 60. + 59., "TOO SLOW")),
+            None
+        );
+        assert_eq!(
+            table.insert(BestTime::new(1., "  a very long name indeed")),
+            Some(0)
+        );
+        assert_eq!(table.entries[0].name, "A VERY LONG");
+    }
+    #[test]
+    fn cut_off_tables_report_the_missing_value() {
+        assert_eq!(
+            malformed("03\n59\n"),
+            "line 2: the last run is missing its name"
+        );
+        assert_eq!(
+            malformed("03\n59\nA\n04\n"),
+            "line 4: the last run is missing its seconds"
+        );
+        // a blank line part way through is a missing value rather than the end of the file
+        assert_eq!(
+            malformed("03\n59\n\n04\n00\nB\n"),
+            "line 3: names should be 1 to 12 characters"
+        );
+    }
+    #[test]
+    fn out_of_range_times_and_names_are_refused() {
+        assert_eq!(
+            malformed("03\n60\nA\n"),
+            "line 2: seconds should be 0 to 59, not `60`"
+        );
+        assert_eq!(
+            malformed("-1\n00\nA\n"),
+            "line 1: minutes should be a number, not `-1`"
+        );
+        assert_eq!(
+            malformed("99999999999\n00\nA\n"),
+            "line 1: minutes should be a number, not `99999999999`"
+        );
+        assert_eq!(
+            malformed("03\n00\nTHIRTEEN CHARS\n"),
+            "line 3: names should be 1 to 12 characters"
+        );
+    }
+    #[test]
+    fn tables_are_swapped_in_whole() {
+        let dir = std::env::temp_dir().join(format!("rustman-scores-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scores.dat");
+        let mut storage = storage::FileStorage { path: path.clone() };
+        assert_eq!(BestTimes::load(&storage), Ok(None));
+        let table = BestTimes::parse(SHIPPED).unwrap();
+        table.save(&mut storage).unwrap();
+        assert_eq!(BestTimes::load(&storage), Ok(Some(table)));
+        std::fs::write(&path, "03\n5x\nBOB\n").unwrap();
+        assert_eq!(
+            BestTimes::load(&storage).unwrap_err().to_string(),
+            "line 2: seconds should be 0 to 59, not `5x`"
+        );
+        let unreadable = storage::FileStorage { path: dir.clone() };
+        assert!(matches!(
+            BestTimes::load(&unreadable),
+            Err(ScoresError::Storage(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+//! suspending a stage part way through and resuming it later, so closing the browser tab doesn't
+//! lose the fight. the puzzle rules save their own state with [puzzle::Engine::save_state], a
+//! snapshot adds the robot, Mega Man's weapon and the rest of the run around it
+use super::puzzle;
+use super::replay::{Replay, robot_named};
+use super::storage::{self, Storage};
+use crate::npc::NPCName;
+/// first line of every snapshot file, followed by the version of the format
+pub const SNAPSHOT_HEADER: &str = "RUSTMAN SNAPSHOT";
+/// bump whenever the format or the puzzle state changes so older snapshots get turned away
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// where a suspended stage is saved
+pub const SNAPSHOT_FILE: &str = "snapshot.dat";
+/// lines before the puzzle state: the header, seed, robot, hitpoints, weapon, defeated robots,
+/// game timer and settings
+const STAGE_LINES: usize = 8;
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub seed: u64,
+    pub robot: NPCName,
+    pub hitpoints: u8,
+    /// `None` being the P Shooter
+    pub weapon: Option<NPCName>,
+    /// robots beaten in earlier stages, whose weapons Mega Man can switch to
+    pub defeated_robots: Vec<NPCName>,
+    /// the game timer's reading, the whole run so far rather than just this stage
+    pub game_timer: f32,
+    /// the player's settings, the piece set comes from the robot's stage and isn't saved
+    pub settings: puzzle::Settings,
+    /// see [puzzle::Engine::save_state]
+    pub puzzle_state: String,
+}
+/// a snapshot that couldn't be read or resumed, with the line it went wrong on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotError {
+    pub line: usize,
+    pub reason: String,
+}
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+impl std::fmt::Display for Snapshot {
+    /// the snapshot file: the header and version, a line for each of the stage's values, then
+    /// the puzzle state. the game timer is saved as its bits so it comes back exactly
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{SNAPSHOT_HEADER} {SNAPSHOT_VERSION}")?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "robot {:?}", self.robot)?;
+        writeln!(f, "hitpoints {}", self.hitpoints)?;
+        match self.weapon {
+            Some(weapon) => writeln!(f, "weapon {weapon:?}")?,
+            None => writeln!(f, "weapon none")?,
+        }
+        write!(f, "defeated")?;
+        for robot in &self.defeated_robots {
+            write!(f, " {robot:?}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "game-timer {:08x}", self.game_timer.to_bits())?;
+        writeln!(f, "settings {}", Replay::format_settings(&self.settings))?;
+        writeln!(f, "{}", self.puzzle_state)
+    }
+}
+impl Snapshot {
+    /// read a snapshot file written by [Snapshot]'s `Display`. the puzzle state is only checked
+    /// once the stage is resumed with [Snapshot::restore_puzzle], as it needs the stage's piece
+    /// set
+    pub fn parse(text: &str) -> Result<Self, SnapshotError> {
+        let error = |line: usize, reason: &str| SnapshotError {
+            line,
+            reason: reason.to_string(),
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        match lines
+            .first()
+            .and_then(|header| header.strip_prefix(SNAPSHOT_HEADER))
+        {
+            Some(version) if version.trim().parse() == Ok(SNAPSHOT_VERSION) => {}
+            Some(_) => {
+                return Err(error(
+                    1,
+                    &format!("only version {SNAPSHOT_VERSION} snapshots can be resumed"),
+                ));
+            }
+            None => {
+                return Err(error(1, "not a snapshot file"));
+            }
+        }
+        // the values on the `idx`th line, which has to start with `keyword`
+        let line = |idx: usize, keyword: &str| {
+            let words: Vec<&str> = lines
+                .get(idx)
+                .ok_or_else(|| error(lines.len(), &format!("missing the {keyword} line")))?
+                .split_whitespace()
+                .collect();
+            match words.split_first() {
+                Some((first, values)) if *first == keyword => Ok((idx + 1, values.to_vec())),
+                _ => Err(error(idx + 1, &format!("expected the {keyword} line"))),
+            }
+        };
+        let (line_no, values) = line(1, "seed")?;
+        let seed = values
+            .first()
+            .and_then(|seed| seed.parse().ok())
+            .ok_or_else(|| error(line_no, "bad seed"))?;
+        let (line_no, values) = line(2, "robot")?;
+        let robot = values
+            .first()
+            .and_then(|name| robot_named(name))
+            .ok_or_else(|| error(line_no, "unknown robot"))?;
+        let (line_no, values) = line(3, "hitpoints")?;
+        let hitpoints = values
+            .first()
+            .and_then(|hitpoints| hitpoints.parse().ok())
+            .filter(|hitpoints| *hitpoints <= super::ROBOT_MAX_HITPOINTS)
+            .ok_or_else(|| error(line_no, "bad hitpoints"))?;
+        let (weapon_line_no, values) = line(4, "weapon")?;
+        let weapon = match values.first() {
+            Some(&"none") => None,
+            Some(name) => {
+                Some(robot_named(name).ok_or_else(|| error(weapon_line_no, "unknown weapon"))?)
+            }
+            None => {
+                return Err(error(weapon_line_no, "missing the weapon"));
+            }
+        };
+        let (line_no, values) = line(5, "defeated")?;
+        let defeated_robots: Vec<NPCName> = values
+            .iter()
+            .map(|name| robot_named(name))
+            .collect::<Option<_>>()
+            .ok_or_else(|| error(line_no, "unknown robot"))?;
+        if defeated_robots.contains(&robot) {
+            return Err(error(line_no, "the robot being fought is already beaten"));
+        }
+        // Mega Man only gets a robot's weapon by beating it
+        if weapon.is_some_and(|weapon| !defeated_robots.contains(&weapon)) {
+            return Err(error(weapon_line_no, "weapon of a robot not yet beaten"));
+        }
+        let (line_no, values) = line(6, "game-timer")?;
+        let game_timer = values
+            .first()
+            .and_then(|bits| u32::from_str_radix(bits, 16).ok())
+            .map(f32::from_bits)
+            .filter(|game_timer| game_timer.is_finite() && *game_timer >= 0.)
+            .ok_or_else(|| error(line_no, "bad game timer"))?;
+        let (line_no, values) = line(7, "settings")?;
+        let settings =
+            Replay::parse_settings(&values).ok_or_else(|| error(line_no, "bad settings"))?;
+        Ok(Self {
+            seed,
+            robot,
+            hitpoints,
+            weapon,
+            defeated_robots,
+            game_timer,
+            settings,
+            puzzle_state: lines[STAGE_LINES..].join("\n"),
+        })
+    }
+    /// rebuild the puzzle rules with the stage's piece set
+    pub fn restore_puzzle(
+        &self,
+        piece_set: puzzle::PieceSet,
+    ) -> Result<puzzle::Engine, SnapshotError> {
+        puzzle::Engine::load_state(
+            puzzle::Settings {
+                piece_set,
+                ..self.settings.clone()
+            },
+            self.seed,
+            &self.puzzle_state,
+        )
+        .map_err(|err| SnapshotError {
+            line: STAGE_LINES + err.line,
+            reason: err.reason,
+        })
+    }
+    /// save the snapshot to `storage`, replacing the stage suspended before it
+    pub fn save(&self, storage: &mut impl Storage) -> Result<(), String> {
+        storage.write(&self.to_string())
+    }
+    /// the snapshot kept in `storage`, `None` when no stage is suspended
+    pub fn load(storage: &impl Storage) -> Result<Option<Self>, String> {
+        storage
+            .read()?
+            .map(|text| Self::parse(&text).map_err(|err| err.to_string()))
+            .transpose()
+    }
+    /// forget the suspended stage once it has been fought to the end, so a finished fight can't
+    /// be picked up again
+    pub fn discard(storage: &mut impl Storage) -> Result<(), String> {
+        storage.remove()
+    }
+}
+/// where the build being run keeps the suspended stage
+pub fn default_storage() -> impl Storage {
+    storage::storage(SNAPSHOT_FILE)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use puzzle::{ClearGravity, Engine, Input, PieceSet, Settings};
+    /// drop pieces down the left of the matrix, shifting left on the ticks between drops
+    fn play(engine: &mut Engine, ticks: std::ops::Range<usize>) {
+        for tick in ticks {
+            let input = if tick % 40 == 0 {
+                Input::HardDrop
+            } else {
+                Input::MoveLeft
+            };
+            engine.update(&[input], 1. / 60.);
+        }
+    }
+    /// Metal Man's stage suspended after 300 ticks, along with the engine that was suspended
+    fn suspended_against_metal_man() -> (Snapshot, Engine) {
+        let settings = Settings {
+            das_ms: 120,
+            clear_gravity: ClearGravity::Cascade,
+            ..Settings::default()
+        };
+        let mut engine = Engine::new(settings.clone(), 77);
+        play(&mut engine, 0..300);
+        let snapshot = Snapshot {
+            seed: 77,
+            robot: NPCName::MetalMan,
+            hitpoints: 9,
+            weapon: Some(NPCName::CrashMan),
+            defeated_robots: vec![NPCName::CrashMan, NPCName::AirMan],
+            game_timer: 123.456,
+            settings,
+            puzzle_state: engine.save_state(),
+        };
+        (snapshot, engine)
+    }
+    #[test]
+    fn resumed_stages_deal_the_same_pieces() {
+        let (snapshot, mut engine) = suspended_against_metal_man();
+        let resumed = Snapshot::parse(&snapshot.to_string()).unwrap();
+        assert_eq!(resumed, snapshot);
+        // the generator's bag comes back too, so the rest of the stage plays out the same
+        let mut resumed = resumed.restore_puzzle(PieceSet::default()).unwrap();
+        play(&mut engine, 300..900);
+        play(&mut resumed, 300..900);
+        assert_eq!(resumed.save_state(), engine.save_state());
+        // a first stage with the P Shooter and nothing beaten yet
+        let first_stage = Snapshot {
+            weapon: None,
+            defeated_robots: Vec::new(),
+            ..snapshot
+        };
+        assert_eq!(
+            Snapshot::parse(&first_stage.to_string()).unwrap(),
+            first_stage
+        );
+    }
+    #[test]
+    fn only_this_versions_snapshots_resume() {
+        let text = suspended_against_metal_man().0.to_string();
+        for header in [
+            "RUSTMAN SNAPSHOT 0",
+            "RUSTMAN SNAPSHOT 2",
+            "RUSTMAN SNAPSHOT",
+        ] {
+            assert_eq!(
+                Snapshot::parse(&text.replacen("RUSTMAN SNAPSHOT 1", header, 1)).unwrap_err(),
+                SnapshotError {
+                    line: 1,
+                    reason: "only version 1 snapshots can be resumed".to_string()
+                }
+            );
+        }
+        // a replay saved where a snapshot was expected
+        for text in ["RUSTMAN REPLAY 2\nseed 77", ""] {
+            assert_eq!(
+                Snapshot::parse(text).unwrap_err().reason,
+                "not a snapshot file"
+            );
+        }
+    }
+    #[test]
+    fn cut_off_snapshots_say_what_is_missing() {
+        let text = suspended_against_metal_man().0.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        let keywords = [
+            "seed",
+            "robot",
+            "hitpoints",
+            "weapon",
+            "defeated",
+            "game-timer",
+            "settings",
+        ];
+        for (kept, keyword) in keywords.iter().enumerate() {
+            assert_eq!(
+                Snapshot::parse(&lines[..kept + 1].join("\n")).unwrap_err(),
+                SnapshotError {
+                    line: kept + 1,
+                    reason: format!("missing the {keyword} line")
+                }
+            );
+        }
+        // cut off in the puzzle state: the stage reads, but can't be resumed
+        let cut_in_the_rows = Snapshot::parse(&lines[..STAGE_LINES + 6].join("\n")).unwrap();
+        assert_eq!(
+            cut_in_the_rows
+                .restore_puzzle(PieceSet::default())
+                .unwrap_err(),
+            SnapshotError {
+                line: STAGE_LINES + 6,
+                reason: "missing row 6".to_string()
+            }
+        );
+    }
+    #[test]
+    fn damaged_boards_are_found_on_resuming() {
+        let text = suspended_against_metal_man().0.to_string();
+        let combo_line = text
+            .lines()
+            .position(|line| line.starts_with("combo"))
+            .unwrap()
+            + 1;
+        let damaged = Snapshot::parse(&text.replacen("combo 0", "combo q", 1)).unwrap();
+        assert_eq!(
+            damaged.restore_puzzle(PieceSet::default()).unwrap_err(),
+            SnapshotError {
+                line: combo_line,
+                reason: "bad combo".to_string()
+            }
+        );
+    }
+    #[test]
+    fn impossible_stages_are_refused() {
+        let text = suspended_against_metal_man().0.to_string();
+        let refused =
+            |from: &str, to: &str| Snapshot::parse(&text.replacen(from, to, 1)).unwrap_err();
+        let error = |line: usize, reason: &str| SnapshotError {
+            line,
+            reason: reason.to_string(),
+        };
+        assert_eq!(refused("seed 77", "seed -1"), error(2, "bad seed"));
+        assert_eq!(
+            refused("robot MetalMan", "robot Bob"),
+            error(3, "unknown robot")
+        );
+        // over the robot's life meter, and over what a u8 holds
+        assert_eq!(
+            refused("hitpoints 9", "hitpoints 17"),
+            error(4, "bad hitpoints")
+        );
+        assert_eq!(
+            refused("hitpoints 9", "hitpoints 300"),
+            error(4, "bad hitpoints")
+        );
+        assert_eq!(
+            refused("weapon CrashMan", "weapon Bob"),
+            error(5, "unknown weapon")
+        );
+        assert_eq!(
+            refused("weapon CrashMan", "weapon QuickMan"),
+            error(5, "weapon of a robot not yet beaten")
+        );
+        assert_eq!(
+            refused("defeated CrashMan", "defeated MetalMan CrashMan"),
+            error(6, "the robot being fought is already beaten")
+        );
+        // the timer is saved as bits, so NaN and negative times can be written by hand
+        let timer = format!("game-timer {:08x}", 123.456_f32.to_bits());
+        for bits in ["7fc00000", "bf800000", "zz"] {
+            assert_eq!(
+                refused(&timer, &format!("game-timer {bits}")),
+                error(7, "bad game timer")
+            );
+        }
+        assert_eq!(refused(" Cascade", ""), error(8, "bad settings"));
+    }
+    #[test]
+    fn snapshots_are_kept_until_discarded() {
+        let path =
+            std::env::temp_dir().join(format!("rustman-snapshot-{}.dat", std::process::id()));
+        let mut storage = storage::FileStorage { path };
+        assert_eq!(Snapshot::load(&storage), Ok(None));
+        let (snapshot, _) = suspended_against_metal_man();
+        snapshot.save(&mut storage).unwrap();
+        assert_eq!(Snapshot::load(&storage), Ok(Some(snapshot)));
+        Snapshot::discard(&mut storage).unwrap();
+        assert_eq!(Snapshot::load(&storage), Ok(None));
+    }
+}
@@ -0,0 +1,156 @@
+//! somewhere text can be kept between runs: the best times table, and also the suspended stage's
+//! [snapshot](super::snapshot) and the last [replay](super::replay). the desktop build keeps them
+//! as files in the player's data directory, the web build in the browser's local storage
+/// the folder in the player's data directory the desktop build saves to
+pub const DATA_FOLDER: &str = "rustman";
+/// where the saved text lives, whichever build is being run
+pub trait Storage {
+    /// the saved text, `None` when nothing has been saved yet
+    fn read(&self) -> Result<Option<String>, String>;
+    /// replace the saved text in one go, so quitting part way through can't leave half of it behind
+    fn write(&mut self, text: &str) -> Result<(), String>;
+    /// forget the saved text, if there is any
+    fn remove(&mut self) -> Result<(), String>;
+}
+/// a file on disk, written to a temporary file next to it first and then renamed over it
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileStorage {
+    pub path: std::path::PathBuf,
+}
+#[cfg(not(target_arch = "wasm32"))]
+impl Storage for FileStorage {
+    fn read(&self) -> Result<Option<String>, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Ok(Some(text)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("failed to read {}: {err}", self.path.display())),
+        }
+    }
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        let temp_path = self.path.with_extension("dat.tmp");
+        // the data directory doesn't exist until the first save
+        self.path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&temp_path, text))
+            .and_then(|_| std::fs::rename(&temp_path, &self.path))
+            .map_err(|err| format!("failed to write {}: {err}", self.path.display()))
+    }
+    fn remove(&mut self) -> Result<(), String> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(format!("failed to remove {}: {err}", self.path.display())),
+        }
+    }
+}
+/// the browser's local storage, where setting a key replaces its value in one go
+#[cfg(target_arch = "wasm32")]
+pub struct BrowserStorage {
+    pub key: String,
+}
+#[cfg(target_arch = "wasm32")]
+impl Storage for BrowserStorage {
+    fn read(&self) -> Result<Option<String>, String> {
+        let storage = quad_storage::STORAGE
+            .lock()
+            .map_err(|err| err.to_string())?;
+        Ok(storage.get(&self.key))
+    }
+    fn write(&mut self, text: &str) -> Result<(), String> {
+        let mut storage = quad_storage::STORAGE
+            .lock()
+            .map_err(|err| err.to_string())?;
+        storage.set(&self.key, text);
+        Ok(())
+    }
+    fn remove(&mut self) -> Result<(), String> {
+        let mut storage = quad_storage::STORAGE
+            .lock()
+            .map_err(|err| err.to_string())?;
+        storage.remove(&self.key);
+        Ok(())
+    }
+}
+/// the player's data directory with [DATA_FOLDER] in it, found from the environment variables
+/// `var` looks up. `None` when none of them are set
+#[cfg(not(target_arch = "wasm32"))]
+fn data_dir(var: impl Fn(&str) -> Option<String>) -> Option<std::path::PathBuf> {
+    use std::path::PathBuf;
+    let home = || var("HOME").map(PathBuf::from);
+    let dir = if cfg!(target_os = "windows") {
+        var("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".local/share")))
+    }?;
+    Some(dir.join(DATA_FOLDER))
+}
+/// where the build being run keeps the file called `name`: in the player's data directory on the
+/// desktop, falling back to the working directory when it can't be found, or in the browser's
+/// local storage under the same name
+pub fn storage(name: &str) -> impl Storage {
+    #[cfg(not(target_arch = "wasm32"))]
+    return FileStorage {
+        path: data_dir(|name| std::env::var(name).ok())
+            .unwrap_or_default()
+            .join(name),
+    };
+    #[cfg(target_arch = "wasm32")]
+    return BrowserStorage {
+        key: name.to_string(),
+    };
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn saves_go_in_the_xdg_data_directory() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            data_dir(env(&[("HOME", "/home/roll")])),
+            Some("/home/roll/.local/share/rustman".into())
+        );
+        assert_eq!(
+            data_dir(env(&[("HOME", "/home/roll"), ("XDG_DATA_HOME", "/data")])),
+            Some("/data/rustman".into())
+        );
+        // an empty XDG_DATA_HOME counts as unset
+        assert_eq!(
+            data_dir(env(&[("HOME", "/home/roll"), ("XDG_DATA_HOME", "")])),
+            Some("/home/roll/.local/share/rustman".into())
+        );
+        assert_eq!(data_dir(env(&[])), None);
+    }
+    #[test]
+    fn the_first_save_makes_the_data_directory() {
+        let dir = std::env::temp_dir().join(format!("rustman-storage-{}", std::process::id()));
+        let mut storage = FileStorage {
+            path: dir.join(DATA_FOLDER).join("replay.dat"),
+        };
+        assert_eq!(storage.read(), Ok(None));
+        storage.write("RUSTMAN REPLAY 2\n").unwrap();
+        storage.write("RUSTMAN REPLAY 2\nseed 4\n").unwrap();
+        assert_eq!(
+            storage.read(),
+            Ok(Some("RUSTMAN REPLAY 2\nseed 4\n".to_string()))
+        );
+        // the temporary file is renamed over the save, not left behind
+        assert!(!dir.join(DATA_FOLDER).join("replay.dat.tmp").exists());
+        storage.remove().unwrap();
+        assert_eq!(storage.remove(), Ok(()));
+        assert_eq!(storage.read(), Ok(None));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}